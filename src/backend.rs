@@ -0,0 +1,300 @@
+use std::{
+    fmt::Display,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use enum_dispatch::enum_dispatch;
+use log::{debug, info};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt as _, AsyncWriteExt},
+};
+
+use crate::{Reg, EC_REGS};
+
+/// Low level access to EC registers
+#[enum_dispatch]
+pub trait EcBackend {
+    async fn read_byte(&mut self, reg: Reg) -> Result<u8>;
+    async fn write_byte(&mut self, reg: Reg, value: u8) -> Result<()>;
+}
+
+/// Way to access EC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// Probe ec-sys, acpi-ec and port-io in this order
+    Auto,
+    /// `ec_sys` kernel module debugfs file (/sys/kernel/debug/ec/ec0/io)
+    EcSys,
+    /// Out-of-tree `acpi_ec` kernel module device (/dev/ec)
+    AcpiEc,
+    /// Direct port I/O on 0x62/0x66 via /dev/port
+    PortIo,
+}
+
+/// EC to work with
+#[derive(Debug, Clone)]
+pub enum EcAccess {
+    /// Real EC accessed via the given backend
+    Hardware(BackendKind),
+    /// Simulated EC backed by register image file. Requires neither root nor Gigabyte laptop
+    Image(PathBuf),
+}
+
+impl EcAccess {
+    pub fn is_simulated(&self) -> bool {
+        matches!(self, EcAccess::Image(_))
+    }
+}
+
+#[enum_dispatch(EcBackend)]
+#[derive(Debug)]
+pub enum Backend {
+    EcSys,
+    AcpiEc,
+    PortIo,
+    SimulatedEc,
+}
+
+impl Backend {
+    pub async fn open(access: &EcAccess) -> Result<Self> {
+        match access {
+            EcAccess::Hardware(kind) => Self::open_hardware(*kind).await,
+            EcAccess::Image(path) => Ok(SimulatedEc::open(path).await?.into()),
+        }
+    }
+
+    async fn open_hardware(kind: BackendKind) -> Result<Self> {
+        match kind {
+            BackendKind::Auto => {
+                let mut errors = vec![];
+                for kind in [BackendKind::EcSys, BackendKind::AcpiEc, BackendKind::PortIo] {
+                    match Box::pin(Self::open_hardware(kind)).await {
+                        Ok(backend) => {
+                            info!("Use {kind:?} EC backend");
+                            return Ok(backend);
+                        }
+                        Err(err) => {
+                            debug!("{kind:?} EC backend is unavailable: {err:#}");
+                            errors.push(format!("{kind:?}: {err:#}"));
+                        }
+                    }
+                }
+                bail!("No EC backend available ({})", errors.join("; "))
+            }
+            BackendKind::EcSys => Ok(EcSys::open().await?.into()),
+            BackendKind::AcpiEc => Ok(AcpiEc::open().await?.into()),
+            BackendKind::PortIo => Ok(PortIo::open().await?.into()),
+        }
+    }
+}
+
+fn modprobe(args: &[&str]) -> Result<()> {
+    _ = Command::new("modprobe")
+        .args(args)
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?
+        .wait()?;
+    Ok(())
+}
+
+async fn open_rw(path: &str) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Open {path}"))
+}
+
+/// Read byte at `offset` of seekable file
+async fn read_at(file: &mut File, offset: u64) -> Result<u8> {
+    _ = file.seek(SeekFrom::Start(offset)).await?;
+    let mut value = [0];
+    _ = file.read_exact(&mut value).await?;
+    Ok(value[0])
+}
+
+/// Write byte at `offset` of seekable file
+async fn write_at(file: &mut File, offset: u64, value: u8) -> Result<()> {
+    _ = file.seek(SeekFrom::Start(offset)).await?;
+    file.write_all(&[value]).await?;
+    Ok(())
+}
+
+/// `ec_sys` debugfs file. Requires debugfs and the module loaded with `write_support=1`,
+/// so it's unavailable with kernel lockdown
+#[derive(Debug)]
+pub struct EcSys {
+    file: File,
+}
+
+impl EcSys {
+    pub async fn open() -> Result<Self> {
+        modprobe(&["ec_sys", "write_support=1"])?;
+        let file = open_rw("/sys/kernel/debug/ec/ec0/io").await?;
+        Ok(Self { file })
+    }
+}
+
+impl EcBackend for EcSys {
+    async fn read_byte(&mut self, reg: Reg) -> Result<u8> {
+        read_at(&mut self.file, reg as u64).await
+    }
+
+    async fn write_byte(&mut self, reg: Reg, value: u8) -> Result<()> {
+        write_at(&mut self.file, reg as u64, value).await
+    }
+}
+
+/// Device of out-of-tree `acpi_ec` kernel module. Works without debugfs
+#[derive(Debug)]
+pub struct AcpiEc {
+    file: File,
+}
+
+impl AcpiEc {
+    pub async fn open() -> Result<Self> {
+        modprobe(&["acpi_ec"])?;
+        let file = open_rw("/dev/ec").await?;
+        Ok(Self { file })
+    }
+}
+
+impl EcBackend for AcpiEc {
+    async fn read_byte(&mut self, reg: Reg) -> Result<u8> {
+        read_at(&mut self.file, reg as u64).await
+    }
+
+    async fn write_byte(&mut self, reg: Reg, value: u8) -> Result<()> {
+        write_at(&mut self.file, reg as u64, value).await
+    }
+}
+
+const EC_DATA_PORT: u64 = 0x62;
+const EC_CMD_PORT: u64 = 0x66;
+const EC_CMD_READ: u8 = 0x80;
+const EC_CMD_WRITE: u8 = 0x81;
+/// Output buffer full: data is ready to be read
+const EC_STATUS_OBF: u8 = 1 << 0;
+/// Input buffer full: EC hasn't consumed previous byte yet
+const EC_STATUS_IBF: u8 = 1 << 1;
+const EC_PORT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Direct access to EC ports with the standard ACPI EC protocol.
+/// Bypasses kernel EC driver, so transactions may interleave with ACPI ones. Use as the last resort
+#[derive(Debug)]
+pub struct PortIo {
+    file: File,
+}
+
+/// EC didn't get ready in time, it may be busy with something else
+#[derive(Debug, Clone, Copy)]
+pub struct EcTimeout {
+    status: u8,
+}
+
+impl Display for EcTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EC port timeout (status {:#04x})", self.status)
+    }
+}
+
+impl std::error::Error for EcTimeout {}
+
+impl PortIo {
+    pub async fn open() -> Result<Self> {
+        let file = open_rw("/dev/port").await?;
+        Ok(Self { file })
+    }
+
+    async fn wait_status(&mut self, mask: u8, set: bool) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            let status = read_at(&mut self.file, EC_CMD_PORT).await?;
+            if (status & mask != 0) == set {
+                return Ok(());
+            }
+            if start.elapsed() > EC_PORT_TIMEOUT {
+                return Err(EcTimeout { status }.into());
+            }
+            tokio::time::sleep(Duration::from_micros(50)).await;
+        }
+    }
+
+    async fn send(&mut self, port: u64, value: u8) -> Result<()> {
+        self.wait_status(EC_STATUS_IBF, false).await?;
+        write_at(&mut self.file, port, value).await
+    }
+}
+
+impl EcBackend for PortIo {
+    async fn read_byte(&mut self, reg: Reg) -> Result<u8> {
+        self.send(EC_CMD_PORT, EC_CMD_READ).await?;
+        self.send(EC_DATA_PORT, reg).await?;
+        self.wait_status(EC_STATUS_OBF, true).await?;
+        read_at(&mut self.file, EC_DATA_PORT).await
+    }
+
+    async fn write_byte(&mut self, reg: Reg, value: u8) -> Result<()> {
+        self.send(EC_CMD_PORT, EC_CMD_WRITE).await?;
+        self.send(EC_DATA_PORT, reg).await?;
+        self.send(EC_DATA_PORT, value).await?;
+        self.wait_status(EC_STATUS_IBF, false).await
+    }
+}
+
+/// Fake EC for tests and development. Registers are bytes of the image file,
+/// so the file can be edited to simulate sensors while gigacenter is running
+#[derive(Debug)]
+pub struct SimulatedEc {
+    file: File,
+    /// Bits of registers EC ignores writes to, like a firmware refusing a setting
+    #[cfg(test)]
+    pub stuck_bits: std::collections::BTreeMap<Reg, u8>,
+}
+
+impl SimulatedEc {
+    /// Open register image. Missing file is created filled with zeros
+    pub async fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await
+            .with_context(|| format!("Open EC image {}", path.display()))?;
+        if file.metadata().await?.len() < EC_REGS as u64 {
+            file.set_len(EC_REGS as u64).await?;
+        }
+        Ok(Self {
+            file,
+            #[cfg(test)]
+            stuck_bits: Default::default(),
+        })
+    }
+}
+
+impl EcBackend for SimulatedEc {
+    async fn read_byte(&mut self, reg: Reg) -> Result<u8> {
+        read_at(&mut self.file, reg as u64).await
+    }
+
+    async fn write_byte(&mut self, reg: Reg, value: u8) -> Result<()> {
+        #[cfg(test)]
+        let value = match self.stuck_bits.get(&reg) {
+            Some(mask) => (value & !mask) | (read_at(&mut self.file, reg as u64).await? & mask),
+            None => value,
+        };
+        write_at(&mut self.file, reg as u64, value).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
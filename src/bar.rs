@@ -0,0 +1,153 @@
+use std::{io::Write as _, time::Duration};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+
+use crate::{
+    common::Handler,
+    fan_speed::FanMode,
+    monitor::{self, Monitor},
+    output::{json, Field},
+};
+
+/// Line format of `gigacenter bar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BarStyle {
+    /// JSON for waybar custom module with `"return-type": "json"`
+    Waybar,
+    /// i3bar protocol, used by i3 and sway bars instead of i3status
+    I3bar,
+    /// Text only, e.g. for polybar script with `tail = true`
+    Plain,
+}
+
+/// Temperatures (°C) of the hotter of CPU and GPU at which the bar turns warning and critical
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub warning: u8,
+    pub critical: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Warning => "warning",
+            Level::Critical => "critical",
+        }
+    }
+
+    /// i3bar has no classes, so levels are shown by color
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Level::Normal => None,
+            Level::Warning => Some("#ffb52a"),
+            Level::Critical => Some("#ff5555"),
+        }
+    }
+}
+
+impl Thresholds {
+    fn level(&self, monitor: &Monitor) -> Level {
+        let temp = (*monitor.cpu_temp).max(*monitor.gpu_temp);
+        if temp >= self.critical {
+            Level::Critical
+        } else if temp >= self.warning {
+            Level::Warning
+        } else {
+            Level::Normal
+        }
+    }
+}
+
+/// Fan mode without fixed speeds, so bar configs may match it, e.g. `fan-fixed` class
+fn fan_mode_name(fan_mode: FanMode) -> String {
+    match fan_mode {
+        FanMode::Fixed { .. } => "fixed".to_owned(),
+        fan_mode => fan_mode.to_string(),
+    }
+}
+
+fn line(style: BarStyle, thresholds: Thresholds, monitor: &Monitor) -> String {
+    let level = thresholds.level(monitor);
+    let mode = fan_mode_name(monitor.fan_mode);
+    let text = format!(
+        "{}°C {}°C {}",
+        *monitor.cpu_temp, *monitor.gpu_temp, monitor.fan_mode
+    );
+    match style {
+        BarStyle::Plain => text,
+        BarStyle::Waybar => json(&Field::Object(vec![
+            ("text", text.as_str().into()),
+            ("alt", mode.as_str().into()),
+            ("tooltip", monitor.to_string().trim_end().into()),
+            (
+                "class",
+                Field::List(vec![
+                    level.name().into(),
+                    format!("fan-{mode}").as_str().into(),
+                ]),
+            ),
+        ])),
+        BarStyle::I3bar => {
+            let mut block = vec![
+                ("name", "gigacenter".into()),
+                ("instance", format!("fan-{mode}").as_str().into()),
+                ("full_text", text.as_str().into()),
+                (
+                    "short_text",
+                    format!("{}°C", (*monitor.cpu_temp).max(*monitor.gpu_temp))
+                        .as_str()
+                        .into(),
+                ),
+                ("urgent", (level == Level::Critical).into()),
+            ];
+            if let Some(color) = level.color() {
+                block.push(("color", color.into()));
+            }
+            json(&Field::List(vec![Field::Object(block)]))
+        }
+    }
+}
+
+/// Print a line per machine state change until Ctrl-C, see [`BarStyle`]
+pub async fn run(
+    ec: &mut Handler,
+    style: BarStyle,
+    thresholds: Thresholds,
+    interval: Duration,
+) -> Result<()> {
+    if thresholds.warning >= thresholds.critical {
+        bail!("Warning temperature must be below critical one");
+    }
+    let mut stdout = std::io::stdout().lock();
+    if style == BarStyle::I3bar {
+        // Header and start of the endless array of status lines
+        writeln!(stdout, "{{\"version\":1}}\n[")?;
+    }
+    let mut last_line = String::new();
+    monitor::follow(ec, interval, |monitor, _| {
+        let line = line(style, thresholds, monitor);
+        // Bars redraw on every line, so the same state isn't repeated
+        if line == last_line {
+            return Ok(());
+        }
+        let separator = if style == BarStyle::I3bar && !last_line.is_empty() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(stdout, "{separator}{line}")?;
+        stdout.flush()?;
+        last_line = line;
+        Ok(())
+    })
+    .await
+}
@@ -1,6 +1,6 @@
 use crate::{
     daemon::codec::DaemonReq,
-    registers::{BAT_THRESHOLD_CUSTOM_MODE, BAT_THRESHOLD_TOGGLE_CUSTOM, CURRENT_BAT_THRESHOLD},
+    profile::profile,
     traits::{ECHandler, InvokeDaemon, ReadEC, WriteEC},
     BitState, RWData,
 };
@@ -49,6 +49,11 @@ impl BatThreshold {
         })
     }
 
+    /// Value to be written, unlike [`Self::actual_value()`] it ignores custom mode state
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
     pub fn actual_value(&self) -> u8 {
         if !self.custom_mode_enabled {
             100
@@ -60,14 +65,15 @@ impl BatThreshold {
 
 impl ReadEC for BatThreshold {
     fn data_to_read() -> Vec<RWData> {
+        let regs = &profile().bat_threshold;
         vec![
             RWData::U1 {
-                reg: BAT_THRESHOLD_CUSTOM_MODE.0,
-                pos: BAT_THRESHOLD_CUSTOM_MODE.1,
+                reg: regs.custom_mode.reg,
+                pos: regs.custom_mode.bit,
                 state: BitState::Disabled,
             },
             RWData::U8 {
-                reg: CURRENT_BAT_THRESHOLD,
+                reg: regs.value,
                 value: 0,
             },
         ]
@@ -76,38 +82,39 @@ impl ReadEC for BatThreshold {
 
 impl WriteEC for BatThreshold {
     fn data_to_write(&self) -> Vec<RWData> {
+        let regs = &profile().bat_threshold;
         // Max value, turn off custom mode
         if self.value == 0x64 {
             vec![
                 RWData::U1 {
-                    reg: BAT_THRESHOLD_TOGGLE_CUSTOM.0,
-                    pos: BAT_THRESHOLD_TOGGLE_CUSTOM.1,
+                    reg: regs.toggle_custom.reg,
+                    pos: regs.toggle_custom.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: BAT_THRESHOLD_CUSTOM_MODE.0,
-                    pos: BAT_THRESHOLD_CUSTOM_MODE.1,
+                    reg: regs.custom_mode.reg,
+                    pos: regs.custom_mode.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U8 {
-                    reg: CURRENT_BAT_THRESHOLD,
+                    reg: regs.value,
                     value: self.value,
                 },
             ]
         } else {
             vec![
                 RWData::U1 {
-                    reg: BAT_THRESHOLD_TOGGLE_CUSTOM.0,
-                    pos: BAT_THRESHOLD_TOGGLE_CUSTOM.1,
+                    reg: regs.toggle_custom.reg,
+                    pos: regs.toggle_custom.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U1 {
-                    reg: BAT_THRESHOLD_CUSTOM_MODE.0,
-                    pos: BAT_THRESHOLD_CUSTOM_MODE.1,
+                    reg: regs.custom_mode.reg,
+                    pos: regs.custom_mode.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U8 {
-                    reg: CURRENT_BAT_THRESHOLD,
+                    reg: regs.value,
                     value: self.value,
                 },
             ]
@@ -3,12 +3,19 @@ use anyhow::Result;
 #[cfg(feature = "gui")]
 use clap::ArgMatches;
 
+use std::{path::PathBuf, str::FromStr};
+
 use clap::{value_parser, Arg, ArgAction, ArgGroup, Command, ValueEnum};
 
+use crate::{
+    backend::BackendKind, bar::BarStyle, fan_curve::FanCurves, fan_speed::FanMode, output::Format,
+    power::PowerSource,
+};
+
 pub fn cli() -> Command {
     #[allow(unused_mut, reason = "Mutable access with `gui` feature")]
     let mut group = ArgGroup::new("exclusive")
-        .args(["show", "fan_mode", "bat_threshold", "daemon"])
+        .args(["show", "fan_mode", "fan_curves", "bat_threshold", "daemon"])
         .multiple(false);
 
     #[cfg(not(feature = "gui"))]
@@ -22,6 +29,92 @@ pub fn cli() -> Command {
         .about("Manage your Gigabyte laptop fan speed and battery threshold on Linux")
         .styles(get_styles())
         .group(group)
+        .subcommand_negates_reqs(true)
+        .subcommand(ec_command())
+        .subcommand(power_command())
+        .subcommand(
+            Command::new("watch")
+                .about("Redraw current machine state live with min/max/avg since start. Uses daemon if available, EC directly otherwise")
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("MS")
+                        .help("Redraw interval in milliseconds")
+                        .value_parser(value_parser!(u64).range(100..))
+                        .default_value("1000"),
+                ),
+        )
+        .subcommand(
+            Command::new("bar")
+                .about("Print a line per machine state change for status bars (waybar, i3bar, polybar). Uses daemon if available, EC directly otherwise")
+                .arg(
+                    Arg::new("style")
+                        .long("style")
+                        .value_name("STYLE")
+                        .help("Line format")
+                        .value_parser(value_parser!(BarStyle))
+                        .default_value("waybar"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("MS")
+                        .help("Update interval in milliseconds")
+                        .value_parser(value_parser!(u64).range(100..))
+                        .default_value("2000"),
+                )
+                .arg(
+                    Arg::new("warning_temp")
+                        .long("warning-temp")
+                        .value_name("TEMP")
+                        .help("Temperature (°C) of the hotter of CPU and GPU at which the bar gets `warning` class")
+                        .value_parser(value_parser!(u8).range(1..=120))
+                        .default_value("80"),
+                )
+                .arg(
+                    Arg::new("critical_temp")
+                        .long("critical-temp")
+                        .value_name("TEMP")
+                        .help("Temperature (°C) at which the bar gets `critical` class (urgent for i3bar)")
+                        .value_parser(value_parser!(u8).range(1..=120))
+                        .default_value("95"),
+                ),
+        )
+        .subcommand(
+            Command::new("fan")
+                .about("Fan mode shortcuts")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("cycle")
+                        .about("Switch to the next fan mode, e.g. on status bar click. Prints the new mode")
+                        .arg(
+                            Arg::new("modes")
+                                .long("modes")
+                                .value_name("MODES")
+                                .help("Comma separated fan modes to cycle through. Any other mode switches to the first one")
+                                .value_delimiter(',')
+                                .value_parser(FanMode::from_str)
+                                .default_value("normal,eco,power,turbo"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Manage daemon configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("check")
+                        .about("Validate daemon config")
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .help("Config to check. Defaults to /etc/gigacenter/config.toml or GIGACENTER_CONFIG")
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                ),
+        )
         .arg(
             Arg::new("show")
                 .short('s')
@@ -36,8 +129,18 @@ pub fn cli() -> Command {
                 .value_name("FAN_MODE")
                 .action(ArgAction::Set)
                 .num_args(0..=1)
-                .help("Get/Set fan speed mode")
-                .value_parser(["normal", "eco", "power", "turbo"]),
+                .help("Get/Set fan speed mode. Takes values: normal, eco, power, turbo or fixed:CPU[,GPU] (in percent)")
+                .value_parser(FanMode::from_str),
+        )
+        .arg(
+            Arg::new("fan_curves")
+                .short('c')
+                .long("fan-curves")
+                .value_name("CURVES")
+                .action(ArgAction::Set)
+                .num_args(0..=1)
+                .help("Get/Set temperature based fan curves driven by daemon. Takes `off` or CPU_CURVE[/GPU_CURVE][;TUNING], where curve is TEMP:SPEED,... (speed in percent), e.g. 50:0,70:50,90:100, and tuning is hysteresis=°C,dwell=SECS,ramp=PERCENT_PER_SEC (defaults: 3, 5, 10)")
+                .value_parser(parse_fan_curves),
         )
         .arg(
             Arg::new("bat_threshold")
@@ -56,10 +159,45 @@ pub fn cli() -> Command {
                 .value_name("DAEMON_COMMAND")
                 .value_parser(value_parser!(DaemonCommands)),
         )
+        .arg(
+            Arg::new("force_model")
+                .long("force-model")
+                .value_name("MODEL")
+                .global(true)
+                .help("Use the model profile instead of detected one and allow writes to EC. Takes embedded profile or the name of file from /etc/gigacenter/models without `.toml` extension"),
+        )
+        .arg(
+            Arg::new("ec_backend")
+                .long("ec-backend")
+                .value_name("BACKEND")
+                .global(true)
+                .help("Way to access EC")
+                .value_parser(value_parser!(BackendKind))
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("ec_image")
+                .long("ec-image")
+                .value_name("FILE")
+                .global(true)
+                .env("GIGACENTER_EC_IMAGE")
+                .help("Use simulated EC backed by 256-byte register image file instead of the real one. The file is created if missing")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .global(true)
+                .help("Output format of queries and errors. JSON and env outputs are stable, see README")
+                .value_parser(value_parser!(Format))
+                .default_value("text"),
+        )
         .arg(
             Arg::new("logs")
                 .short('l')
                 .long("enable-logs")
+                .global(true)
                 .help("Enable logs")
                 .action(ArgAction::SetTrue),
         )
@@ -69,7 +207,7 @@ pub fn cli() -> Command {
 
     #[allow(unused_mut, reason = "Mutable access with `gui` feature")]
     let mut base_after_help =
-        "NOTE: Currently it's tested for Aorus 16X. For other models, use it at your own risk!"
+        "NOTE: Currently it's tested for Aorus 16X. Unknown models are read-only unless `--force-model` is given, use it at your own risk!"
             .to_owned();
 
     #[cfg(not(feature = "gui"))]
@@ -83,6 +221,197 @@ pub fn cli() -> Command {
     cli
 }
 
+/// Low level EC access for porting to new models
+fn ec_command() -> Command {
+    Command::new("ec")
+        .about("Inspect raw EC registers. Useful to find registers of a new laptop model")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("dump")
+                .about("Print all EC registers as hex table")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Also save the dump to file to compare it later with `ec diff`")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("watch")
+                        .short('w')
+                        .long("watch")
+                        .help("Redraw the dump live highlighting changed registers. Helps to find sensor registers")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("output"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("MS")
+                        .help("Redraw interval of `--watch` in milliseconds")
+                        .value_parser(value_parser!(u64).range(100..))
+                        .default_value("1000"),
+                ),
+        )
+        .subcommand(
+            Command::new("read")
+                .about("Read single EC register. Goes through daemon if available")
+                .arg(reg_arg())
+                .arg(
+                    Arg::new("bit")
+                        .long("bit")
+                        .value_name("N")
+                        .help("Read only bit N (0 - 7) of the register")
+                        .value_parser(value_parser!(u8).range(0..=7)),
+                )
+                .arg(
+                    Arg::new("u16")
+                        .long("u16")
+                        .help("Read big endian 16-bit value from the register and the next one")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("bit"),
+                )
+                .arg(
+                    Arg::new("little_endian")
+                        .long("little-endian")
+                        .help("Read 16-bit value as little endian")
+                        .action(ArgAction::SetTrue)
+                        .requires("u16"),
+                ),
+        )
+        .subcommand(
+            Command::new("write")
+                .about("Write single EC register or bit. Goes through daemon if available. Previous value is printed, so the write can be reverted")
+                .arg(reg_arg())
+                .arg(
+                    Arg::new("value")
+                        .value_name("VALUE")
+                        .required(true)
+                        .help("Byte to write (decimal or 0x prefixed hex), 0/1 with `--bit` or 16-bit value with `--u16`")
+                        .value_parser(parse_u16),
+                )
+                .arg(
+                    Arg::new("bit")
+                        .long("bit")
+                        .value_name("N")
+                        .help("Write only bit N (0 - 7) of the register")
+                        .value_parser(value_parser!(u8).range(0..=7)),
+                )
+                .arg(
+                    Arg::new("u16")
+                        .long("u16")
+                        .help("Write big endian 16-bit value to the register and the next one")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("bit"),
+                )
+                .arg(
+                    Arg::new("little_endian")
+                        .long("little-endian")
+                        .help("Write 16-bit value as little endian")
+                        .action(ArgAction::SetTrue)
+                        .requires("u16"),
+                )
+                .arg(
+                    Arg::new("confirm")
+                        .long("i-know-what-im-doing")
+                        .help("Confirm the write. Wrong EC values may damage your hardware")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show registers and bits changed between two dumps. Takes dumps saved by `ec dump --output` or raw 256-byte EC images")
+                .arg(
+                    Arg::new("old")
+                        .value_name("OLD")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("new")
+                        .value_name("NEW")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+}
+
+/// Rules applied by daemon on AC/battery switch
+fn power_command() -> Command {
+    Command::new("power")
+        .about("Get/Set fan mode and battery threshold applied by daemon when laptop switches between AC and battery. Without arguments prints all rules")
+        .arg(
+            Arg::new("source")
+                .value_name("SOURCE")
+                .help("Power source of the rule: ac or battery")
+                .value_parser(PowerSource::from_str),
+        )
+        .arg(
+            Arg::new("fan_mode")
+                .short('f')
+                .long("fan-mode")
+                .value_name("FAN_MODE")
+                .help("Fan mode applied on the power source. Takes the same values as `--fan-mode` of gigacenter")
+                .requires("source")
+                .value_parser(FanMode::from_str),
+        )
+        .arg(
+            Arg::new("bat_threshold")
+                .short('b')
+                .long("bat-threshold")
+                .value_name("THRESHOLD")
+                .help("Battery threshold applied on the power source. Takes values from 60 to 100 (in percent)")
+                .requires("source")
+                .value_parser(value_parser!(u8).range(60..=100)),
+        )
+        .arg(
+            Arg::new("clear")
+                .long("clear")
+                .help("Remove the rule, so nothing is changed on switch to the power source")
+                .action(ArgAction::SetTrue)
+                .requires("source")
+                .conflicts_with_all(["fan_mode", "bat_threshold"]),
+        )
+}
+
+fn reg_arg() -> Arg {
+    Arg::new("reg")
+        .value_name("REG")
+        .required(true)
+        .help("Register address (decimal or 0x prefixed hex)")
+        .value_parser(parse_u8)
+}
+
+/// Byte written as decimal or 0x prefixed hex
+fn parse_u8(s: &str) -> Result<u8, String> {
+    let res = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|err| format!("{err}. Expected value from 0 to 255 or from 0x00 to 0xff"))
+}
+
+/// 16-bit value written as decimal or 0x prefixed hex
+fn parse_u16(s: &str) -> Result<u16, String> {
+    let res = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|err| format!("{err}. Expected value from 0 to 65535 or from 0x0000 to 0xffff"))
+}
+
+/// `off` disables fan curves
+fn parse_fan_curves(s: &str) -> Result<Option<FanCurves>, String> {
+    if s == "off" {
+        return Ok(None);
+    }
+    FanCurves::from_str(s)
+        .map(Some)
+        .map_err(|err| format!("{err:#}"))
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum DaemonCommands {
     /// Run daemon
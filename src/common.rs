@@ -1,4 +1,4 @@
-use crate::{daemon::client::DaemonClient, EmbeddedController};
+use crate::{backend::EcAccess, daemon::client::DaemonClient, EmbeddedController};
 use anyhow::{bail, Result};
 use enum_dispatch::enum_dispatch;
 
@@ -13,15 +13,21 @@ pub const EXIT_MSG: &str =
     "Run GigaCenter as root or install systemd service with `gigacenter daemon install`";
 
 impl Handler {
-    pub async fn new() -> Result<Self> {
-        let daemon_client = DaemonClient::connect().await;
-        if let Ok(dc) = daemon_client {
-            log::info!("Connected to daemon");
-            return Ok(Handler::from(dc));
+    pub async fn new(access: &EcAccess) -> Result<Self> {
+        // Simulated EC is always used directly, so the real daemon is never touched
+        if access.is_simulated() {
+            return Ok(Handler::from(EmbeddedController::new(access).await?));
         }
-        let ec = EmbeddedController::new().await;
+        let daemon_client = match DaemonClient::connect().await {
+            Ok(dc) => {
+                log::info!("Connected to daemon");
+                return Ok(Handler::from(dc));
+            }
+            Err(err) => err,
+        };
+        let ec = EmbeddedController::new(access).await;
         if let Ok(ec) = ec {
-            log::warn!("Failed to connect to daemon. Use as standalone");
+            log::warn!("Failed to connect to daemon: {daemon_client:#}. Use as standalone");
             return Ok(Handler::from(ec));
         }
         bail!("{EXIT_MSG}")
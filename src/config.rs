@@ -0,0 +1,217 @@
+#![expect(
+    forbidden_lint_groups,
+    reason = "serde derive allows `unused_extern_crates` forbidden by `rust_2018_idioms`"
+)]
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::{
+    daemon::server::{DAEMON_UDS_ENV, DAEMON_UDS_PATH},
+    fan_curve::FanCurves,
+    fan_speed::FanMode,
+    power::PowerRules,
+    profile::Profile,
+    WRITE_TIMEOUT_MS,
+};
+
+pub const CONFIG_PATH: &str = "/etc/gigacenter/config.toml";
+/// Where the daemon keeps settings applied by clients
+pub const STATE_DIR: &str = "/var/lib/gigacenter";
+/// Environment variable to override [`CONFIG_PATH`]
+pub const CONFIG_ENV: &str = "GIGACENTER_CONFIG";
+
+/// Daemon configuration. Every setting is optional. Fan mode, battery threshold and fan curves
+/// are applied at daemon startup, so they are defaults which clients may change later
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    /// Model profile used instead of detected one, like `--force-model`
+    pub model: Option<String>,
+    pub socket: SocketConfig,
+    #[serde(deserialize_with = "parse_str")]
+    pub fan_mode: Option<FanMode>,
+    pub bat_threshold: Option<u8>,
+    /// How often fan curves are applied (in milliseconds)
+    pub polling_interval_ms: u64,
+    /// Used only if no curves were set (or disabled) by clients
+    #[serde(deserialize_with = "parse_str")]
+    pub fan_curves: Option<FanCurves>,
+    pub state_dir: PathBuf,
+    /// Used only if no rules were set by clients
+    pub power: PowerRules,
+    /// Members may read any EC register through daemon, others only the ones of model profile.
+    /// Empty disables it, so only root may
+    pub admin_group: String,
+    pub power_profiles: PowerProfilesConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct SocketConfig {
+    pub path: String,
+    /// Unix permissions of socket. Daemon checks permissions by itself, so it's open by default
+    pub mode: u32,
+    /// Owner group of socket. Its members may change settings, others only read them.
+    /// Empty leaves it to root only
+    pub group: String,
+}
+
+/// Fan modes of desktop power profiles (power-profiles-daemon D-Bus interface served by daemon)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PowerProfilesConfig {
+    /// Off by default: power-profiles-daemon owns the same bus name and fails to start if
+    /// the daemon takes it first, so it has to be disabled before enabling this
+    pub enabled: bool,
+    #[serde(deserialize_with = "parse_value")]
+    pub power_saver: FanMode,
+    #[serde(deserialize_with = "parse_value")]
+    pub balanced: FanMode,
+    #[serde(deserialize_with = "parse_value")]
+    pub performance: FanMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            model: None,
+            socket: SocketConfig::default(),
+            fan_mode: None,
+            bat_threshold: None,
+            // Greater than write timeout, so curve writes never wait for throttling
+            polling_interval_ms: WRITE_TIMEOUT_MS as u64 + 500,
+            fan_curves: None,
+            state_dir: PathBuf::from(STATE_DIR),
+            power: PowerRules::default(),
+            admin_group: "wheel".to_owned(),
+            power_profiles: PowerProfilesConfig::default(),
+        }
+    }
+}
+
+impl Default for PowerProfilesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            power_saver: FanMode::Eco,
+            balanced: FanMode::Normal,
+            performance: FanMode::Power,
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            path: DAEMON_UDS_PATH.to_owned(),
+            mode: 0o666,
+            group: "gigacenter".to_owned(),
+        }
+    }
+}
+
+/// Values written as strings in the same format as CLI takes
+pub fn parse_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err| D::Error::custom(format!("{err:#}")))
+        })
+        .transpose()
+}
+
+/// Same as [`parse_str`] for required values
+pub fn parse_value<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(|err| D::Error::custom(format!("{err:#}")))
+}
+
+impl Config {
+    /// Config file path: [`CONFIG_ENV`] or [`CONFIG_PATH`]
+    pub fn path() -> PathBuf {
+        std::env::var_os(CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(CONFIG_PATH))
+    }
+
+    /// Load and check config. Missing file gives default config
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::load_from(&path)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Read config {}", path.display()))?;
+        let config = toml::from_str::<Self>(&content)
+            .with_context(|| format!("Parse config {}", path.display()))?;
+        config
+            .check()
+            .with_context(|| format!("Invalid config {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if let Some(model) = &self.model {
+            _ = Profile::load(model)?;
+        }
+        if self.socket.path.is_empty() {
+            bail!("socket.path must not be empty")
+        }
+        if self.state_dir.as_os_str().is_empty() {
+            bail!("state_dir must not be empty")
+        }
+        if self.socket.mode > 0o777 {
+            bail!("socket.mode must be from 0o000 to 0o777")
+        }
+        if let Some(threshold) = self.bat_threshold {
+            if !(60..=100).contains(&threshold) {
+                bail!("bat_threshold must be from 60 to 100 (in percent)")
+            }
+        }
+        self.power.check().context("Invalid power rules")?;
+        let profiles = &self.power_profiles;
+        if profiles.power_saver == profiles.balanced
+            || profiles.balanced == profiles.performance
+            || profiles.power_saver == profiles.performance
+        {
+            bail!("power_profiles must have different fan modes, otherwise active profile is ambiguous")
+        }
+        if self.polling_interval_ms < WRITE_TIMEOUT_MS as u64 {
+            bail!("polling_interval_ms must be at least {WRITE_TIMEOUT_MS}, EC writes are throttled anyway")
+        }
+        Ok(())
+    }
+
+    /// Socket path. [`DAEMON_UDS_ENV`] takes precedence over config
+    pub fn socket_path(&self) -> String {
+        std::env::var(DAEMON_UDS_ENV).unwrap_or_else(|_| self.socket.path.clone())
+    }
+
+    pub fn polling_interval(&self) -> Duration {
+        Duration::from_millis(self.polling_interval_ms)
+    }
+}
@@ -1,39 +1,127 @@
+use std::time::Duration;
+
 use anyhow::{bail, Context, Result};
 use futures::{SinkExt as _, StreamExt};
 use tokio::net::UnixStream;
 
 use crate::{
+    fan_curve::FanCurves,
+    power::PowerRules,
     traits::{ECHandler, ReadEC, WriteResult},
     RWData,
 };
 
 use super::{
-    codec::{bind_transport_client, DaemonReq, DaemonResp, FramedClient},
-    server::DAEMON_UDS_PATH,
+    codec::{bind_transport_client, DaemonReq, DaemonResp, Features, FramedClient, Hello},
+    server::daemon_uds_path,
+    subscribe::DaemonEvent,
 };
 
 #[derive(Debug)]
 pub struct DaemonClient {
     stream: FramedClient,
+    /// Supported by both client and daemon
+    features: Features,
 }
 
 impl DaemonClient {
     pub async fn connect() -> Result<Self> {
-        let stream = UnixStream::connect(DAEMON_UDS_PATH)
+        let mut stream = UnixStream::connect(daemon_uds_path())
             .await
             .context("Connect to daemon")?;
+        let features = Hello::client(&mut stream).await?;
         let stream = bind_transport_client(stream);
-        Ok(Self { stream })
+        Ok(Self { stream, features })
+    }
+
+    /// Fail early if daemon is from older release lacking the feature
+    fn require(&self, feature: Features, name: &str) -> Result<()> {
+        if !self.features.contains(feature) {
+            bail!("Daemon doesn't support {name}. Restart it after update");
+        }
+        Ok(())
+    }
+
+    /// Send request and wait for reply. [`DaemonResp::Error`] is turned into error
+    async fn request(&mut self, req: DaemonReq) -> Result<DaemonResp> {
+        self.stream.send(req).await?;
+        self.reply().await
+    }
+
+    async fn reply(&mut self) -> Result<DaemonResp> {
+        match self.stream.next().await {
+            Some(Ok(DaemonResp::Error(err))) => Err(err.into()),
+            Some(Ok(resp)) => Ok(resp),
+            Some(Err(err)) => Err(err).context("Read daemon reply"),
+            None => bail!("Daemon closed connection"),
+        }
+    }
+
+    /// Write result with failed verification turned into error
+    async fn write(&mut self, req: DaemonReq) -> Result<WriteResult> {
+        match self.request(req).await? {
+            DaemonResp::WriteResult(WriteResult::Failed(failure)) => Err(failure.into()),
+            DaemonResp::WriteResult(res) => Ok(res),
+            _ => bail!("Unknown daemon reply"),
+        }
+    }
+
+    /// Fan curves currently driven by daemon
+    pub async fn fan_curves(&mut self) -> Result<Option<FanCurves>> {
+        self.require(Features::FAN_CURVES, "fan curves")?;
+        let DaemonResp::FanCurves(curves) = self.request(DaemonReq::GetFanCurves).await? else {
+            bail!("Unknown daemon reply")
+        };
+        Ok(curves)
+    }
+
+    /// Make daemon drive fans by curves. `None` disables curves
+    pub async fn set_fan_curves(&mut self, curves: Option<FanCurves>) -> Result<WriteResult> {
+        self.require(Features::FAN_CURVES, "fan curves")?;
+        self.write(DaemonReq::SetFanCurves(curves)).await
+    }
+
+    /// Fan mode and battery threshold applied by daemon on AC/battery switch
+    pub async fn power_rules(&mut self) -> Result<PowerRules> {
+        self.require(Features::POWER_RULES, "power rules")?;
+        let DaemonResp::PowerRules(rules) = self.request(DaemonReq::GetPowerRules).await? else {
+            bail!("Unknown daemon reply")
+        };
+        Ok(rules)
+    }
+
+    pub async fn set_power_rules(&mut self, rules: PowerRules) -> Result<WriteResult> {
+        self.require(Features::POWER_RULES, "power rules")?;
+        self.write(DaemonReq::SetPowerRules(rules)).await
+    }
+
+    /// Make daemon push events to this connection. Only [`Self::next_event()`] may be used afterwards,
+    /// so subscribe with a dedicated connection
+    pub async fn subscribe(&mut self, interval: Duration) -> Result<()> {
+        self.require(Features::SUBSCRIBE, "subscriptions")?;
+        self.stream
+            .send(DaemonReq::Subscribe {
+                interval_ms: interval.as_millis() as u64,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn next_event(&mut self) -> Result<DaemonEvent> {
+        let DaemonResp::Event(event) = self.reply().await? else {
+            bail!("Unknown daemon reply")
+        };
+        Ok(event)
     }
 }
 
 impl ECHandler for DaemonClient {
     async fn read_data<T: ReadEC>(&mut self) -> Result<Vec<RWData>> {
-        let data_to_read = T::data_to_read();
-        self.stream
-            .send(DaemonReq::ReadValues(data_to_read))
-            .await?;
-        let Some(Ok(DaemonResp::ReadValues(data))) = self.stream.next().await else {
+        self.read_values(T::data_to_read()).await
+    }
+
+    async fn read_values(&mut self, data: Vec<RWData>) -> Result<Vec<RWData>> {
+        let DaemonResp::ReadValues(data) = self.request(DaemonReq::ReadValues(data)).await? else {
             bail!("Unknown daemon reply")
         };
         Ok(data)
@@ -43,11 +131,6 @@ impl ECHandler for DaemonClient {
         &mut self,
         data: &(impl crate::traits::WriteEC + crate::traits::InvokeDaemon),
     ) -> Result<WriteResult> {
-        let req = data.daemon_action();
-        self.stream.send(req).await?;
-        let Some(Ok(DaemonResp::WriteResult(res))) = self.stream.next().await else {
-            bail!("Unknown daemon reply")
-        };
-        Ok(res)
+        self.write(data.daemon_action()).await
     }
 }
@@ -1,26 +1,193 @@
-use std::{io::ErrorKind, marker::PhantomData};
+use std::{fmt::Display, io::ErrorKind, marker::PhantomData, time::Duration};
 
+use anyhow::{bail, Context, Result};
 use rkyv::{rancor::Error as RkyvError, Archive, Deserialize, Serialize};
-use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::UnixStream,
+};
 use tokio_util::{
     bytes::Buf as _,
     codec::{Decoder, Encoder, Framed},
 };
 
-use crate::{bat::BatThreshold, fan_speed::FanMode, traits::WriteResult, RWData};
+use super::{error::DaemonError, subscribe::DaemonEvent};
+use crate::{
+    bat::BatThreshold, fan_curve::FanCurves, fan_speed::FanMode, power::PowerRules, raw::RawWrite,
+    traits::WriteResult, RWData,
+};
+
+/// Protocol spoken over the daemon socket. Peers of the same major version are compatible.
+/// rkyv doesn't promise archived enums keep their layout when variants are added, so a new
+/// request or reply only bumps minor version if it's gated on a [`Features`] bit like
+/// [`Features::SUBSCRIBE`], i.e. sent only to peers announcing it, and archived sizes pinned by
+/// `archived_sizes_are_stable` test stay the same. Anything else bumps major version
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+/// Starts the hello, so daemons and clients of releases without handshake are told apart
+const HELLO_MAGIC: &[u8; 4] = b"GIGA";
+const HELLO_LEN: usize = 16;
+/// Daemons without handshake wait for a frame of garbage length instead of answering.
+/// Daemon waits as long for client hello, so silent clients don't hold connections forever
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Optional requests supported by peer. Unknown bits of newer peers are ignored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u64);
+
+impl Features {
+    pub const FAN_CURVES: Self = Self(1);
+    pub const POWER_RULES: Self = Self(1 << 1);
+    pub const SUBSCRIBE: Self = Self(1 << 2);
+
+    /// Features of this release
+    pub const fn all() -> Self {
+        Self(Self::FAN_CURVES.0 | Self::POWER_RULES.0 | Self::SUBSCRIBE.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Features both peers support
+    pub const fn common(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// Exchanged once right after connect, before any [`DaemonReq`]. Client speaks first, daemon
+/// answers with its own hello and closes connection if major versions differ.
+/// Encoded by hand, so its layout never changes whatever happens to rkyv types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    pub version: ProtocolVersion,
+    pub features: Features,
+}
+
+impl Hello {
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: Features::all(),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HELLO_LEN] {
+        let mut bytes = [0; HELLO_LEN];
+        bytes[..4].copy_from_slice(HELLO_MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.major.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.version.minor.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.features.0.to_le_bytes());
+        bytes
+    }
+
+    /// `None` if peer doesn't start with hello, i.e. it's from release without handshake
+    fn from_bytes(bytes: &[u8; HELLO_LEN]) -> Option<Self> {
+        if &bytes[..4] != HELLO_MAGIC {
+            return None;
+        }
+        let u16_at = |pos: usize| u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+        let mut features = [0; 8];
+        features.copy_from_slice(&bytes[8..]);
+        Some(Self {
+            version: ProtocolVersion {
+                major: u16_at(4),
+                minor: u16_at(6),
+            },
+            features: Features(u64::from_le_bytes(features)),
+        })
+    }
+
+    /// Whether this side can talk to the peer
+    pub fn is_compatible(&self, peer: &Self) -> bool {
+        self.version.major == peer.version.major
+    }
+
+    /// Client side of handshake. Returns features supported by both sides
+    pub async fn client(stream: &mut UnixStream) -> Result<Features> {
+        let hello = Self::current();
+        stream.write_all(&hello.to_bytes()).await?;
+        let mut bytes = [0; HELLO_LEN];
+        let read = tokio::time::timeout(HELLO_TIMEOUT, stream.read_exact(&mut bytes)).await;
+        let daemon = match read {
+            Ok(Ok(_)) => Self::from_bytes(&bytes),
+            Ok(Err(err)) if err.kind() == ErrorKind::UnexpectedEof => None,
+            Ok(Err(err)) => return Err(err).context("Read daemon hello"),
+            Err(_) => None,
+        };
+        let Some(daemon) = daemon else {
+            bail!("Daemon doesn't support protocol handshake, it's from older release than this client (protocol {PROTOCOL_VERSION}). Restart it after update or reinstall with `gigacenter --daemon install`")
+        };
+        if !hello.is_compatible(&daemon) {
+            bail!(
+                "Daemon speaks protocol {}, but this client speaks {PROTOCOL_VERSION}. Use client and daemon of the same release",
+                daemon.version
+            )
+        }
+        Ok(hello.features.common(daemon.features))
+    }
+
+    /// Daemon side of handshake. Returns client hello if client may go on
+    pub async fn daemon(stream: &mut UnixStream) -> Result<Self> {
+        let mut bytes = [0; HELLO_LEN];
+        _ = tokio::time::timeout(HELLO_TIMEOUT, stream.read_exact(&mut bytes))
+            .await
+            .context("Client didn't send hello in time")?
+            .context("Read client hello")?;
+        let Some(client) = Self::from_bytes(&bytes) else {
+            bail!("Client doesn't support protocol handshake, it's from older release")
+        };
+        let hello = Self::current();
+        stream.write_all(&hello.to_bytes()).await?;
+        if !hello.is_compatible(&client) {
+            bail!(
+                "Client speaks protocol {}, but daemon speaks {PROTOCOL_VERSION}",
+                client.version
+            )
+        }
+        Ok(client)
+    }
+}
 
 #[derive(Debug, Archive, Serialize, Deserialize)]
 pub enum DaemonReq {
     SetFanMode(FanMode),
     SetBatThreshold(BatThreshold),
     ReadValues(Vec<RWData>),
+    /// `None` disables fan curves
+    SetFanCurves(Option<FanCurves>),
+    GetFanCurves,
+    /// Write single register or bit as is. Allowed to root and the daemon owner only
+    WriteRaw(RawWrite),
+    /// Rule for the current power source is applied right away
+    SetPowerRules(PowerRules),
+    GetPowerRules,
+    /// Turn connection into stream of [`DaemonResp::Event`]. Monitor is pushed every `interval_ms`,
+    /// changes made by other clients right away. No requests may follow
+    Subscribe {
+        interval_ms: u64,
+    },
 }
 
 #[derive(Debug, Archive, Serialize, Deserialize)]
 pub enum DaemonResp {
     ReadValues(Vec<RWData>),
     WriteResult(WriteResult),
-    Error(String),
+    FanCurves(Option<FanCurves>),
+    PowerRules(PowerRules),
+    Event(DaemonEvent),
+    Error(DaemonError),
 }
 
 pub type FramedClient = Framed<UnixStream, DaemonCodec<RoleClient>>;
@@ -153,3 +320,17 @@ impl Encoder<DaemonResp> for DaemonCodec<RoleServer> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::subscribe::ArchivedDaemonEvent;
+
+    /// Peer reads the root from the end of the frame, so a size change breaks older peers
+    #[test]
+    fn archived_sizes_are_stable() {
+        assert_eq!(size_of::<ArchivedDaemonReq>(), 32);
+        assert_eq!(size_of::<ArchivedDaemonResp>(), 36);
+        assert_eq!(size_of::<ArchivedDaemonEvent>(), 32);
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use zbus::{
+    fdo,
+    message::Header,
+    names::BusName,
+    object_server::{InterfaceRef, SignalEmitter},
+    zvariant::Value,
+    Connection,
+};
+
+use crate::{
+    bat::BatThreshold,
+    fan_speed::{CpuFanSpeed, FanMode, GpuFanSpeed},
+    monitor::Monitor,
+    temp::{CpuTemp, GpuTemp},
+    traits::WriteResult,
+};
+
+use super::{
+    error::DaemonError,
+    peer::Peer,
+    power_profiles::serve_power_profiles,
+    server::{set_bat_threshold, set_fan_mode, DaemonState},
+    subscribe::DaemonEvent,
+};
+
+pub const DBUS_NAME: &str = "org.gigacenter.Daemon";
+pub const DBUS_PATH: &str = "/org/gigacenter/Daemon";
+/// Polkit action allowing to change settings over D-Bus
+pub const POLKIT_ACTION: &str = "org.gigacenter.daemon.change-settings";
+/// Temperatures and fan speeds are polled this rarely for `PropertiesChanged`, as nobody may listen.
+/// Settings changed through the daemon are signalled right away
+const SENSORS_INTERVAL: Duration = Duration::from_secs(10);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait PolkitAuthority {
+    /// Returns `(is_authorized, is_challenge, details)`
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// `org.gigacenter.Daemon` object, a D-Bus face of the same daemon state socket clients use
+pub struct DaemonInterface {
+    state: Arc<DaemonState>,
+}
+
+impl From<DaemonError> for fdo::Error {
+    fn from(err: DaemonError) -> Self {
+        let msg = err.to_string();
+        match err {
+            DaemonError::InvalidArgument(_) => fdo::Error::InvalidArgs(msg),
+            DaemonError::PermissionDenied(_) => fdo::Error::AccessDenied(msg),
+            DaemonError::Unsupported(_) => fdo::Error::NotSupported(msg),
+            DaemonError::EcIo(_) | DaemonError::Busy(_) | DaemonError::Internal(_) => {
+                fdo::Error::Failed(msg)
+            }
+        }
+    }
+}
+
+/// EC access error classified the same way as for socket clients
+pub fn ec_error(err: anyhow::Error) -> fdo::Error {
+    DaemonError::from_ec(&err).into()
+}
+
+/// Write result of settings change, anything but done is an error on D-Bus
+pub fn write_error(res: Result<WriteResult, DaemonError>) -> fdo::Result<()> {
+    match res? {
+        WriteResult::Done => Ok(()),
+        WriteResult::Busy => Err(fdo::Error::Failed("EC is busy, try again later".to_owned())),
+        WriteResult::Failed(failure) => Err(fdo::Error::Failed(failure.to_string())),
+    }
+}
+
+/// Check that sender of D-Bus call may change settings.
+/// Those allowed to write to the socket are trusted, others need admin password asked by polkit
+pub async fn authorize(
+    conn: &Connection,
+    header: &Header<'_>,
+    state: &DaemonState,
+) -> fdo::Result<()> {
+    let sender = header
+        .sender()
+        .ok_or_else(|| fdo::Error::AccessDenied("Unknown sender".to_owned()))?;
+    let cred = fdo::DBusProxy::new(conn)
+        .await?
+        .get_connection_credentials(BusName::from(sender.clone()))
+        .await?;
+    let peer = Peer::from_dbus(&cred)
+        .ok_or_else(|| fdo::Error::AccessDenied("Unknown sender user".to_owned()))?;
+    if state.may_write(&peer) {
+        return Ok(());
+    }
+    let uid = peer.uid;
+    let subject = (
+        "system-bus-name",
+        HashMap::from([("name", Value::from(sender.as_str()))]),
+    );
+    // Let polkit agent ask the user for password
+    const ALLOW_USER_INTERACTION: u32 = 1;
+    let (authorized, _, _) = PolkitAuthorityProxy::new(conn)
+        .await?
+        .check_authorization(
+            &subject,
+            POLKIT_ACTION,
+            HashMap::new(),
+            ALLOW_USER_INTERACTION,
+            "",
+        )
+        .await
+        .map_err(|err| {
+            warn!("Polkit check of uid {uid} failed: {err}");
+            fdo::Error::AccessDenied(format!("Polkit check failed: {err}"))
+        })?;
+    if !authorized {
+        warn!("D-Bus write by uid {uid} rejected by polkit");
+        return Err(fdo::Error::AccessDenied(format!(
+            "Not authorized for {POLKIT_ACTION}"
+        )));
+    }
+    Ok(())
+}
+
+#[zbus::interface(name = "org.gigacenter.Daemon")]
+impl DaemonInterface {
+    /// Fan mode as accepted by `gigacenter -f`, e.g. `eco` or `fixed:40,60`
+    #[zbus(property)]
+    async fn fan_mode(&self) -> fdo::Result<String> {
+        let fan_mode = FanMode::current_mode(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(fan_mode.to_string())
+    }
+
+    /// Battery charge threshold in percent, 100 if disabled
+    #[zbus(property)]
+    async fn bat_threshold(&self) -> fdo::Result<u8> {
+        let threshold = BatThreshold::current_state(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(threshold.actual_value())
+    }
+
+    #[zbus(property)]
+    async fn cpu_temp(&self) -> fdo::Result<u8> {
+        let temp = CpuTemp::current_state(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(*temp)
+    }
+
+    #[zbus(property)]
+    async fn gpu_temp(&self) -> fdo::Result<u8> {
+        let temp = GpuTemp::current_state(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(*temp)
+    }
+
+    #[zbus(property)]
+    async fn cpu_fan_speed(&self) -> fdo::Result<u16> {
+        let speed = CpuFanSpeed::current_state(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(*speed)
+    }
+
+    #[zbus(property)]
+    async fn gpu_fan_speed(&self) -> fdo::Result<u16> {
+        let speed = GpuFanSpeed::current_state(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(*speed)
+    }
+
+    /// Same as `gigacenter -f`, disables fan curves
+    async fn set_fan_mode(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        fan_mode: &str,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header, &self.state).await?;
+        let fan_mode = fan_mode
+            .parse::<FanMode>()
+            .map_err(|err| fdo::Error::InvalidArgs(err.to_owned()))?;
+        write_error(set_fan_mode(&self.state, fan_mode).await)
+    }
+
+    /// Same as `gigacenter -b`
+    async fn set_bat_threshold(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        threshold: u8,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header, &self.state).await?;
+        write_error(set_bat_threshold(&self.state, threshold).await)
+    }
+}
+
+/// Own `org.gigacenter.Daemon` on the system bus and emit `PropertiesChanged` on daemon events
+pub async fn serve_dbus(state: Arc<DaemonState>) -> Result<()> {
+    let connection = zbus::connection::Builder::system()
+        .context("Connect to system bus")?
+        .name(DBUS_NAME)
+        .context("Request bus name")?
+        .serve_at(
+            DBUS_PATH,
+            DaemonInterface {
+                state: state.clone(),
+            },
+        )?
+        .build()
+        .await
+        .with_context(|| format!("Own {DBUS_NAME} on system bus"))?;
+    let iface = connection
+        .object_server()
+        .interface::<_, DaemonInterface>(DBUS_PATH)
+        .await?;
+    info!("Serving {DBUS_NAME} on system bus");
+    if state.config.power_profiles.enabled {
+        let cloned = (connection.clone(), state.clone());
+        _ = tokio::spawn(async {
+            if let Err(err) = serve_power_profiles(cloned.0, cloned.1).await {
+                warn!("Power profiles are unavailable: {err:#}");
+            }
+        });
+    }
+
+    let mut subscription = state.subscriptions.subscribe(SENSORS_INTERVAL);
+    let mut last: Option<Monitor> = None;
+    while let Some(event) = subscription.next().await {
+        if let Err(err) = emit_changes(&iface, &event, &mut last).await {
+            debug!("Failed to emit PropertiesChanged: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Emit `PropertiesChanged` for values differing from the last snapshot
+async fn emit_changes(
+    iface: &InterfaceRef<DaemonInterface>,
+    event: &DaemonEvent,
+    last: &mut Option<Monitor>,
+) -> zbus::Result<()> {
+    let emitter: &SignalEmitter<'_> = iface.signal_emitter();
+    let obj = iface.get().await;
+    match event {
+        DaemonEvent::Monitor(monitor) => {
+            let changed = |field: fn(&Monitor) -> u16| {
+                last.as_ref()
+                    .is_none_or(|last| field(last) != field(monitor))
+            };
+            let fan_mode_changed = last
+                .as_ref()
+                .is_none_or(|last| last.fan_mode != monitor.fan_mode);
+            if fan_mode_changed {
+                obj.fan_mode_changed(emitter).await?;
+            }
+            if changed(|m| m.bat_threshold.actual_value().into()) {
+                obj.bat_threshold_changed(emitter).await?;
+            }
+            if changed(|m| (*m.cpu_temp).into()) {
+                obj.cpu_temp_changed(emitter).await?;
+            }
+            if changed(|m| (*m.gpu_temp).into()) {
+                obj.gpu_temp_changed(emitter).await?;
+            }
+            if changed(|m| *m.cpu_fan_speed) {
+                obj.cpu_fan_speed_changed(emitter).await?;
+            }
+            if changed(|m| *m.gpu_fan_speed) {
+                obj.gpu_fan_speed_changed(emitter).await?;
+            }
+            *last = Some(*monitor);
+        }
+        DaemonEvent::FanMode(fan_mode) => {
+            obj.fan_mode_changed(emitter).await?;
+            if let Some(last) = last {
+                last.fan_mode = *fan_mode;
+            }
+        }
+        DaemonEvent::BatThreshold(_) => obj.bat_threshold_changed(emitter).await?,
+        DaemonEvent::FanCurves(_) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::fmt::Display;
+
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::{backend::EcTimeout, ec::ReadOnlyModel};
+
+/// Why daemon failed the request. Sent as [`super::codec::DaemonResp::Error`], connection stays open
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum DaemonError {
+    /// Request is malformed or its values are out of range
+    InvalidArgument(String),
+    /// Reading or writing EC failed
+    EcIo(String),
+    /// EC didn't respond in time. The request may be retried later
+    Busy(String),
+    /// Client isn't allowed to send the request
+    PermissionDenied(String),
+    /// Request can't be fulfilled on this laptop model
+    Unsupported(String),
+    /// Daemon failed on its own, e.g. to save its state
+    Internal(String),
+}
+
+impl DaemonError {
+    /// Classify error of EC access
+    pub fn from_ec(err: &anyhow::Error) -> Self {
+        let msg = format!("{err:#}");
+        if err.chain().any(|err| err.is::<EcTimeout>()) {
+            Self::Busy(msg)
+        } else if err.chain().any(|err| err.is::<ReadOnlyModel>()) {
+            Self::Unsupported(msg)
+        } else {
+            Self::EcIo(msg)
+        }
+    }
+
+    pub fn internal(err: &anyhow::Error) -> Self {
+        Self::Internal(format!("{err:#}"))
+    }
+}
+
+impl Display for DaemonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonError::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+            DaemonError::EcIo(msg) => write!(f, "EC I/O error: {msg}"),
+            DaemonError::Busy(msg) => write!(f, "EC is busy, try again later: {msg}"),
+            DaemonError::PermissionDenied(msg) => write!(f, "Permission denied: {msg}"),
+            DaemonError::Unsupported(msg) => write!(f, "Unsupported on this model: {msg}"),
+            DaemonError::Internal(msg) => write!(f, "Daemon error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
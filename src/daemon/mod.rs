@@ -1,3 +1,12 @@
 pub mod client;
 pub mod codec;
+pub mod dbus;
+pub mod error;
+pub mod peer;
+pub mod power;
+pub mod power_profiles;
+pub mod resume;
 pub mod server;
+pub mod settings;
+pub mod subscribe;
+pub mod systemd;
@@ -0,0 +1,137 @@
+use std::{ffi::CString, os::fd::AsRawFd as _};
+
+use anyhow::{Context, Result};
+use libc::{geteuid, gid_t, uid_t};
+use tokio::net::UnixStream;
+use zbus::fdo::ConnectionCredentials;
+
+/// Credentials of connected client taken with `SO_PEERCRED` and `SO_PEERGROUPS`
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub uid: uid_t,
+    pub pid: Option<i32>,
+    /// Primary and supplementary groups
+    groups: Vec<gid_t>,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> Result<Self> {
+        let cred = stream.peer_cred().context("Get peer credentials")?;
+        let mut groups = vec![cred.gid()];
+        // Groups are captured at connect, so they can't change later. Kernels before 4.13 don't
+        // have them, the process is asked then, though it might have changed groups or exited
+        match peer_groups(stream) {
+            Some(peer_groups) => groups.extend(peer_groups),
+            None => groups.extend(cred.pid().map(process_groups).unwrap_or_default()),
+        }
+        Ok(Self {
+            uid: cred.uid(),
+            pid: cred.pid(),
+            groups,
+        })
+    }
+
+    /// Sender of D-Bus call. `None` if the bus didn't tell its user
+    pub fn from_dbus(cred: &ConnectionCredentials) -> Option<Self> {
+        let pid = cred.process_id().and_then(|pid| i32::try_from(pid).ok());
+        // Older buses don't pass groups
+        let groups = match (cred.unix_group_ids(), pid) {
+            (Some(groups), _) => groups.clone(),
+            (None, Some(pid)) => process_groups(pid),
+            (None, None) => vec![],
+        };
+        Some(Self {
+            uid: cred.unix_user_id()?,
+            pid,
+            groups,
+        })
+    }
+
+    /// Root or the user running daemon, e.g. simulated one
+    pub fn is_owner(&self) -> bool {
+        self.uid == 0 || self.uid == unsafe { geteuid() }
+    }
+
+    pub fn in_group(&self, gid: gid_t) -> bool {
+        self.groups.contains(&gid)
+    }
+}
+
+/// Supplementary groups of socket peer. `None` if kernel doesn't support `SO_PEERGROUPS`
+fn peer_groups(stream: &UnixStream) -> Option<Vec<gid_t>> {
+    let mut groups = vec![0; 64];
+    loop {
+        let mut len = (groups.len() * size_of::<gid_t>()) as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        let count = len as usize / size_of::<gid_t>();
+        if res == 0 {
+            groups.truncate(count);
+            return Some(groups);
+        }
+        // Buffer is too small, `len` is the needed size then
+        if std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE)
+            && count > groups.len()
+        {
+            groups.resize(count, 0);
+            continue;
+        }
+        return None;
+    }
+}
+
+/// Supplementary groups of the process. Empty if it's gone
+fn process_groups(pid: i32) -> Vec<gid_t> {
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{pid}/status")) else {
+        return vec![];
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|gid| gid.parse::<gid_t>().ok())
+        .collect()
+}
+
+/// Id of the group. `None` if there is no such group
+pub fn group_id(name: &str) -> Result<Option<gid_t>> {
+    let c_name = CString::new(name).context("Group name contains NUL")?;
+    let mut group = unsafe { std::mem::zeroed::<libc::group>() };
+    let mut buf = vec![0; 16 * 1024];
+    let mut res = std::ptr::null_mut();
+    let code = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut res,
+        )
+    };
+    if code != 0 {
+        return Err(std::io::Error::from_raw_os_error(code))
+            .with_context(|| format!("Look up group {name}"));
+    }
+    Ok((!res.is_null()).then_some(group.gr_gid))
+}
+
+/// Login name of the user. `None` if there is no such user
+pub fn user_name(uid: uid_t) -> Option<String> {
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut buf = vec![0; 16 * 1024];
+    let mut res = std::ptr::null_mut();
+    let code = unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut res) };
+    if code != 0 || res.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
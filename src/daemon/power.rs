@@ -0,0 +1,40 @@
+use std::{sync::Arc, time::Duration};
+
+use log::{debug, error, info, warn};
+
+use crate::{power::PowerSource, traits::WriteResult};
+
+use super::server::{apply_power_rule, DaemonState};
+
+/// Reading sysfs is cheap, so poll often enough to apply rules right after plugging
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Apply power rule every time laptop switches between AC and battery.
+/// Power source detected at startup counts as a switch, as it may have changed while the daemon was stopped
+pub async fn watch_power_source(state: Arc<DaemonState>) {
+    let supply_dir = PowerSource::supply_dir();
+    let mut last_source = None;
+    let mut failed = false;
+    loop {
+        match PowerSource::current(&supply_dir) {
+            Ok(Some(source)) if last_source != Some(source) => {
+                info!("Power source is {source}");
+                last_source = Some(source);
+                let rule = *state.power_rules.lock().await.rule(source);
+                match apply_power_rule(&state, rule).await {
+                    Ok(WriteResult::Done) => debug!("Power rule applied: {rule}"),
+                    Ok(res) => warn!("Power rule {rule} is not applied: {res:?}"),
+                    Err(err) => error!("Failed to apply power rule: {err:#}"),
+                }
+            }
+            Ok(_) => {}
+            // Log once, not every poll
+            Err(err) if !failed => {
+                failed = true;
+                warn!("Failed to detect power source: {err:#}");
+            }
+            Err(_) => {}
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
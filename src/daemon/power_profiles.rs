@@ -0,0 +1,359 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result};
+use futures::StreamExt as _;
+use log::{debug, info};
+use tokio::sync::Mutex;
+use zbus::{
+    fdo::{self, RequestNameFlags},
+    message::Header,
+    names::OwnedUniqueName,
+    object_server::{InterfaceRef, SignalEmitter},
+    zvariant::Value,
+    Connection,
+};
+
+use crate::{config::PowerProfilesConfig, fan_speed::FanMode};
+
+use super::{
+    dbus::{authorize, ec_error, write_error},
+    server::{set_fan_mode, DaemonState},
+    subscribe::DaemonEvent,
+};
+
+/// Name and path of power-profiles-daemon, which desktop power menus talk to
+pub const PPD_NAME: &str = "net.hadess.PowerProfiles";
+pub const PPD_PATH: &str = "/net/hadess/PowerProfiles";
+
+const POWER_SAVER: &str = "power-saver";
+const BALANCED: &str = "balanced";
+const PERFORMANCE: &str = "performance";
+
+/// Profile requested by application for a while, e.g. by a game
+struct Hold {
+    cookie: u32,
+    profile: &'static str,
+    reason: String,
+    application_id: String,
+    sender: OwnedUniqueName,
+}
+
+#[derive(Default)]
+struct Holds {
+    list: Vec<Hold>,
+    next_cookie: u32,
+    /// Profile active before the first hold, restored after the last one is released
+    before_holds: Option<&'static str>,
+}
+
+impl Holds {
+    /// Profile applied while there are holds. Power saver wins, as in power-profiles-daemon
+    fn held_profile(&self) -> Option<&'static str> {
+        self.list
+            .iter()
+            .map(|hold| hold.profile)
+            .min_by_key(|profile| *profile != POWER_SAVER)
+    }
+}
+
+/// `net.hadess.PowerProfiles` object switching fan modes configured for desktop power profiles.
+/// Methods take `&self`, so a caller waiting for polkit doesn't block property reads.
+/// Holds are locked only once the caller is authorized
+pub struct PowerProfiles {
+    state: Arc<DaemonState>,
+    holds: Mutex<Holds>,
+}
+
+impl PowerProfiles {
+    fn config(&self) -> &PowerProfilesConfig {
+        &self.state.config.power_profiles
+    }
+
+    fn fan_mode(&self, profile: &str) -> Option<FanMode> {
+        let config = self.config();
+        match profile {
+            POWER_SAVER => Some(config.power_saver),
+            BALANCED => Some(config.balanced),
+            PERFORMANCE => Some(config.performance),
+            _ => None,
+        }
+    }
+
+    /// Profile of fan mode. Fan modes of no profile, e.g. set with `gigacenter -f turbo`, are shown as balanced
+    fn profile(&self, fan_mode: FanMode) -> &'static str {
+        let config = self.config();
+        if fan_mode == config.power_saver {
+            POWER_SAVER
+        } else if fan_mode == config.performance {
+            PERFORMANCE
+        } else {
+            BALANCED
+        }
+    }
+
+    async fn current_profile(&self) -> fdo::Result<&'static str> {
+        let fan_mode = FanMode::current_mode(&mut *self.state.ec.lock().await)
+            .await
+            .map_err(ec_error)?;
+        Ok(self.profile(fan_mode))
+    }
+
+    async fn switch(&self, profile: &str) -> fdo::Result<()> {
+        let fan_mode = self
+            .fan_mode(profile)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown profile {profile}")))?;
+        debug!("Power profile {profile} requested");
+        write_error(set_fan_mode(&self.state, fan_mode).await)
+    }
+
+    /// Drop matching holds and switch to the profile of remaining ones or to the one before holds
+    async fn release_holds(
+        &self,
+        emitter: &SignalEmitter<'_>,
+        release: impl Fn(&Hold) -> bool,
+    ) -> fdo::Result<bool> {
+        let mut holds = self.holds.lock().await;
+        let count = holds.list.len();
+        holds.list.retain(|hold| !release(hold));
+        if holds.list.len() == count {
+            return Ok(false);
+        }
+        if let Some(profile) = holds.held_profile().or_else(|| holds.before_holds.take()) {
+            self.switch(profile).await?;
+        }
+        // Unlocked first, the signal reads holds
+        drop(holds);
+        self.active_profile_holds_changed(emitter).await?;
+        Ok(true)
+    }
+}
+
+#[zbus::interface(name = "net.hadess.PowerProfiles")]
+impl PowerProfiles {
+    #[zbus(property)]
+    async fn active_profile(&self) -> fdo::Result<String> {
+        Ok(self.current_profile().await?.to_owned())
+    }
+
+    #[zbus(property)]
+    async fn set_active_profile(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Option<Header<'_>>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        profile: String,
+    ) -> fdo::Result<()> {
+        let header = header.ok_or_else(|| fdo::Error::AccessDenied("Unknown sender".to_owned()))?;
+        authorize(conn, &header, &self.state).await?;
+        let mut holds = self.holds.lock().await;
+        // Unknown profile or failed switch keeps holds
+        self.switch(&profile).await?;
+        // Profile chosen by user cancels holds
+        holds.before_holds = None;
+        let released = std::mem::take(&mut holds.list);
+        drop(holds);
+        if released.is_empty() {
+            return Ok(());
+        }
+        for hold in released {
+            Self::profile_released(&emitter, hold.cookie).await?;
+        }
+        self.active_profile_holds_changed(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    async fn profiles(&self) -> Vec<HashMap<&'static str, Value<'static>>> {
+        [POWER_SAVER, BALANCED, PERFORMANCE]
+            .into_iter()
+            .map(|profile| {
+                HashMap::from([
+                    ("Profile", Value::from(profile)),
+                    ("Driver", Value::from("gigacenter")),
+                    ("PlatformDriver", Value::from("gigacenter")),
+                ])
+            })
+            .collect()
+    }
+
+    #[zbus(property)]
+    async fn actions(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    async fn performance_inhibited(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property)]
+    async fn performance_degraded(&self) -> String {
+        String::new()
+    }
+
+    #[zbus(property)]
+    async fn active_profile_holds(&self) -> Vec<HashMap<&'static str, Value<'static>>> {
+        self.holds
+            .lock()
+            .await
+            .list
+            .iter()
+            .map(|hold| {
+                HashMap::from([
+                    ("ApplicationId", Value::from(hold.application_id.clone())),
+                    ("Profile", Value::from(hold.profile)),
+                    ("Reason", Value::from(hold.reason.clone())),
+                ])
+            })
+            .collect()
+    }
+
+    #[zbus(property)]
+    async fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_owned()
+    }
+
+    /// Keep profile until released or the application exits. Returns cookie to release it with
+    async fn hold_profile(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        profile: String,
+        reason: String,
+        application_id: String,
+    ) -> fdo::Result<u32> {
+        authorize(conn, &header, &self.state).await?;
+        let profile = match profile.as_str() {
+            POWER_SAVER => POWER_SAVER,
+            PERFORMANCE => PERFORMANCE,
+            _ => {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Only {POWER_SAVER} and {PERFORMANCE} may be held"
+                )))
+            }
+        };
+        let sender = header
+            .sender()
+            .ok_or_else(|| fdo::Error::AccessDenied("Unknown sender".to_owned()))?;
+        let mut holds = self.holds.lock().await;
+        if holds.list.is_empty() {
+            holds.before_holds = Some(self.current_profile().await?);
+        }
+        holds.next_cookie += 1;
+        let cookie = holds.next_cookie;
+        info!("{application_id} holds {profile} profile: {reason}");
+        holds.list.push(Hold {
+            cookie,
+            profile,
+            reason,
+            application_id,
+            sender: sender.to_owned().into(),
+        });
+        if let Some(profile) = holds.held_profile() {
+            self.switch(profile).await?;
+        }
+        // Unlocked first, the signal reads holds
+        drop(holds);
+        self.active_profile_holds_changed(&emitter).await?;
+        Ok(cookie)
+    }
+
+    async fn release_profile(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        cookie: u32,
+    ) -> fdo::Result<()> {
+        if !self
+            .release_holds(&emitter, |hold| hold.cookie == cookie)
+            .await?
+        {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "No hold with cookie {cookie}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hold was released not by its application, e.g. user chose another profile
+    #[zbus(signal)]
+    async fn profile_released(emitter: &SignalEmitter<'_>, cookie: u32) -> zbus::Result<()>;
+}
+
+/// Own `net.hadess.PowerProfiles` on the connection and keep active profile in sync with fan mode.
+/// Fails if power-profiles-daemon runs, both can't serve desktop at once
+pub async fn serve_power_profiles(connection: Connection, state: Arc<DaemonState>) -> Result<()> {
+    _ = connection
+        .request_name_with_flags(PPD_NAME, RequestNameFlags::DoNotQueue.into())
+        .await
+        .with_context(|| format!("Own {PPD_NAME}, is power-profiles-daemon running?"))?;
+    _ = connection
+        .object_server()
+        .at(
+            PPD_PATH,
+            PowerProfiles {
+                state: state.clone(),
+                holds: Mutex::default(),
+            },
+        )
+        .await?;
+    let iface = connection
+        .object_server()
+        .interface::<_, PowerProfiles>(PPD_PATH)
+        .await?;
+    info!("Serving {PPD_NAME} on system bus");
+
+    let mut owner_changes = fdo::DBusProxy::new(&connection)
+        .await?
+        .receive_name_owner_changed()
+        .await?;
+    // Fan mode comes with events, changes made past the daemon with monitor polled for others
+    let mut subscription = state.subscriptions.subscribe_events();
+    let mut last_fan_mode = None;
+    loop {
+        tokio::select! {
+            event = subscription.next() => {
+                let fan_mode = match event {
+                    Some(DaemonEvent::FanMode(fan_mode)) => fan_mode,
+                    Some(DaemonEvent::Monitor(monitor)) => monitor.fan_mode,
+                    Some(_) => continue,
+                    None => return Ok(()),
+                };
+                if last_fan_mode.replace(fan_mode) != Some(fan_mode) {
+                    emit_profile_changed(&iface).await;
+                }
+            }
+            Some(change) = owner_changes.next() => {
+                let Ok(args) = change.args() else {
+                    continue;
+                };
+                if args.new_owner().is_some() {
+                    continue;
+                }
+                // Holds of exited applications are released
+                let name = args.name().to_string();
+                let res = iface
+                    .get()
+                    .await
+                    .release_holds(iface.signal_emitter(), |hold| hold.sender.as_str() == name)
+                    .await;
+                match res {
+                    Ok(true) => info!("Holds of {name} released as it exited"),
+                    Ok(false) => {}
+                    Err(err) => debug!("Failed to release holds of {name}: {err}"),
+                }
+            }
+        }
+    }
+}
+
+async fn emit_profile_changed(iface: &InterfaceRef<PowerProfiles>) {
+    let res = iface
+        .get()
+        .await
+        .active_profile_changed(iface.signal_emitter())
+        .await;
+    if let Err(err) = res {
+        debug!("Failed to emit PropertiesChanged: {err}");
+    }
+}
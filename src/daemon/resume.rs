@@ -0,0 +1,45 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use log::{debug, info};
+
+use super::server::{restore_settings, DaemonState};
+
+/// EC may still be initializing right after wake up
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Login1Manager {
+    /// Emitted with `true` before suspend and with `false` after resume
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Restore settings every time the system resumes from suspend or hibernation
+pub async fn watch_resume(state: Arc<DaemonState>) -> Result<()> {
+    let connection = zbus::Connection::system()
+        .await
+        .context("Connect to system bus")?;
+    let manager = Login1ManagerProxy::new(&connection)
+        .await
+        .context("Connect to logind")?;
+    let mut signals = manager
+        .receive_prepare_for_sleep()
+        .await
+        .context("Subscribe to PrepareForSleep")?;
+    debug!("Watching for resume");
+    while let Some(signal) = signals.next().await {
+        if signal.args()?.start {
+            continue;
+        }
+        info!("System resumed, checking settings");
+        tokio::time::sleep(RESUME_DELAY).await;
+        restore_settings(&state).await;
+    }
+    Ok(())
+}
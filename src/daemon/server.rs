@@ -1,38 +1,187 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, path::Path, sync::Arc, time::Duration};
 
 use anyhow::{bail, Context, Result};
 use futures::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use libc::gid_t;
+use log::{debug, error, info, warn};
 use std::{fs, os::unix::fs::PermissionsExt};
 use tokio::{net::UnixListener, sync::Mutex};
 
 use crate::{
+    backend::EcAccess,
+    bat::BatThreshold,
+    config::{Config, CONFIG_PATH},
     daemon::codec::bind_transport_server,
-    traits::{ECHandler, WriteResult},
-    EmbeddedController,
+    fan_curve::{CurveController, FanCurves, FAN_CURVE_FILE},
+    fan_speed::FanMode,
+    power::{PowerRule, PowerRules, PowerSource},
+    profile::profile,
+    raw::RawValue,
+    temp::{CpuTemp, GpuTemp},
+    traits::{ECHandler, WriteFailure, WriteResult},
+    EmbeddedController, RWData, Reg,
 };
 
-use super::codec::{DaemonReq, DaemonResp, FramedServer};
+use super::{
+    codec::{DaemonReq, DaemonResp, FramedServer, Hello},
+    dbus::serve_dbus,
+    error::DaemonError,
+    peer::{group_id, Peer},
+    power::watch_power_source,
+    resume::watch_resume,
+    settings::Settings,
+    subscribe::{run_monitor, stream_events, DaemonEvent, Subscriptions},
+    systemd::{listen_fds, notify, run_watchdog, watchdog_timeout},
+};
 
 #[cfg(feature = "self-packed")]
 pub const SYSTEMD_SERVICE: &[u8] = include_bytes!("../../assets/gigacenter-daemon.service");
+#[cfg(feature = "self-packed")]
+pub const SYSTEMD_SOCKET: &[u8] = include_bytes!("../../assets/gigacenter-daemon.socket");
+#[cfg(feature = "self-packed")]
+pub const DBUS_POLICY: &[u8] = include_bytes!("../../assets/org.gigacenter.Daemon.conf");
+#[cfg(feature = "self-packed")]
+pub const POLKIT_POLICY: &[u8] = include_bytes!("../../assets/org.gigacenter.daemon.policy");
+
+#[cfg(feature = "self-packed")]
+const DBUS_POLICY_PATH: &str = "/etc/dbus-1/system.d/org.gigacenter.Daemon.conf";
+#[cfg(feature = "self-packed")]
+const POLKIT_POLICY_PATH: &str = "/usr/share/polkit-1/actions/org.gigacenter.daemon.policy";
+
+pub const DAEMON_UDS_PATH: &str = "/run/gigacenter/gigacenter.sock";
+/// Environment variable to override socket path, e.g. to run simulated daemon next to the real one
+pub const DAEMON_UDS_ENV: &str = "GIGACENTER_SOCKET";
 
-pub const DAEMON_UDS_PATH: &str = "/tmp/gigacenter";
+/// Socket path clients connect to. Broken config is ignored here, the daemon reports it at startup
+pub fn daemon_uds_path() -> String {
+    Config::load()
+        .unwrap_or_else(|err| {
+            debug!("Ignore config: {err:#}");
+            Config::default()
+        })
+        .socket_path()
+}
+
+/// State shared between all daemon connections
+#[derive(Debug)]
+pub struct DaemonState {
+    pub ec: Mutex<EmbeddedController>,
+    /// Fan curves driven by daemon. `None` if fan mode is managed manually
+    pub fan_curves: Mutex<Option<FanCurves>>,
+    /// Settings EC is kept in
+    pub settings: Mutex<Settings>,
+    /// Settings applied on AC/battery switch
+    pub power_rules: Mutex<PowerRules>,
+    pub subscriptions: Subscriptions,
+    /// Registers of model profile anyone may read
+    pub readable_regs: BTreeSet<Reg>,
+    /// Members may read any register
+    pub admin_gid: Option<gid_t>,
+    /// Members may change settings
+    pub write_gid: Option<gid_t>,
+    pub config: Config,
+}
 
-pub async fn start_daemon() -> Result<()> {
-    let ec = Arc::new(Mutex::new(EmbeddedController::new().await?));
-    _ = tokio::fs::remove_file(DAEMON_UDS_PATH).await;
-    let listener = UnixListener::bind(DAEMON_UDS_PATH).context("Create listener")?;
-    let perms = fs::Permissions::from_mode(0o777);
+impl DaemonState {
+    /// Root and socket group members may change settings, whether over socket or D-Bus
+    pub fn may_write(&self, peer: &Peer) -> bool {
+        peer.is_owner() || self.write_gid.is_some_and(|gid| peer.in_group(gid))
+    }
+}
 
-    fs::set_permissions(DAEMON_UDS_PATH, perms)?;
+pub async fn start_daemon(access: &EcAccess, config: &Config) -> Result<()> {
+    // Environment is changed before tasks that may read it are spawned
+    let passed_listener = listen_fds()?;
+    // Curves set by clients (or explicitly disabled by them) take precedence over config
+    let fan_curves = if config.state_dir.join(FAN_CURVE_FILE).exists() {
+        FanCurves::load(&config.state_dir).unwrap_or_else(|err| {
+            warn!("Failed to load saved fan curves: {err:#}");
+            None
+        })
+    } else {
+        config.fan_curves.clone()
+    };
+    if let Some(curves) = &fan_curves {
+        info!("Fan curves: {curves}");
+    }
+    let power_rules = PowerRules::load(&config.state_dir)
+        .unwrap_or_else(|err| {
+            warn!("Failed to load saved power rules: {err:#}");
+            None
+        })
+        .unwrap_or(config.power);
+    let saved = Settings::load(&config.state_dir).unwrap_or_else(|err| {
+        warn!("Failed to load saved settings: {err:#}");
+        Settings::default()
+    });
+    let settings = saved.or(Settings {
+        fan_mode: config.fan_mode,
+        bat_threshold: config.bat_threshold,
+    });
+    let admin_gid = resolve_group(&config.admin_group, "read any register")?;
+    let write_gid = resolve_group(&config.socket.group, "change settings")?;
+    let state = Arc::new(DaemonState {
+        ec: Mutex::new(EmbeddedController::new(access).await?),
+        fan_curves: Mutex::new(fan_curves),
+        settings: Mutex::new(settings),
+        power_rules: Mutex::new(power_rules),
+        subscriptions: Subscriptions::default(),
+        readable_regs: profile().readable_regs(),
+        admin_gid,
+        write_gid,
+        config: config.clone(),
+    });
+    restore_settings(&state).await;
+    _ = tokio::spawn(run_fan_curves(state.clone(), config.polling_interval()));
+    let cloned = state.clone();
+    _ = tokio::spawn(async {
+        if let Err(err) = watch_resume(cloned).await {
+            warn!("Settings won't be restored after resume: {err:#}");
+        }
+    });
+    let cloned = state.clone();
+    _ = tokio::spawn(async {
+        if let Err(err) = serve_dbus(cloned).await {
+            warn!("D-Bus interface is unavailable: {err:#}");
+        }
+    });
+    _ = tokio::spawn(watch_power_source(state.clone()));
+    _ = tokio::spawn(run_monitor(state.clone()));
+    if let Some(timeout) = watchdog_timeout() {
+        _ = tokio::spawn(run_watchdog(state.clone(), timeout));
+    }
+    let uds_path = config.socket_path();
+    let listener = match passed_listener {
+        Some(listener) => {
+            let addr = listener.local_addr()?;
+            info!("Using socket {:?} passed by systemd", addr.as_pathname());
+            if addr.as_pathname() != Some(Path::new(&uds_path)) {
+                warn!("Clients connect to {uds_path}, update ListenStream= of the socket unit");
+            } else if let Some(gid) = write_gid {
+                if let Err(err) = std::os::unix::fs::chown(&uds_path, None, Some(gid)) {
+                    warn!("Failed to change socket group: {err}");
+                }
+            }
+            listener
+        }
+        None => bind_socket(Path::new(&uds_path), config.socket.mode, write_gid)?,
+    };
+    notify("READY=1");
     info!("Daemon ready for incoming connections");
     loop {
         match listener.accept().await.context("Create IPC listener") {
-            Ok((stream, _)) => {
-                let cloned = ec.clone();
-                let transport = bind_transport_server(stream);
-                _ = tokio::spawn(async {
+            Ok((mut stream, _)) => {
+                let cloned = state.clone();
+                _ = tokio::spawn(async move {
+                    let client = match Hello::daemon(&mut stream).await {
+                        Ok(client) => client,
+                        Err(err) => {
+                            warn!("Connection rejected: {err:#}");
+                            return;
+                        }
+                    };
+                    debug!("Client speaks protocol {}", client.version);
+                    let transport = bind_transport_server(stream);
                     if let Err(err) = handle_incoming(transport, cloned).await {
                         error!("Error daemon request: {err}");
                     };
@@ -43,43 +192,392 @@ pub async fn start_daemon() -> Result<()> {
     }
 }
 
-pub async fn handle_incoming(
-    mut stream: FramedServer,
-    ec: Arc<Mutex<EmbeddedController>>,
-) -> Result<()> {
+/// Group by name from config. Empty name or missing group leave it to root only
+fn resolve_group(name: &str, purpose: &str) -> Result<Option<gid_t>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let gid = group_id(name)?;
+    if gid.is_none() {
+        warn!("Group {name} doesn't exist, only root may {purpose}");
+    }
+    Ok(gid)
+}
+
+/// Socket directory is created by root in `/run`, so nobody can squat on the path before daemon starts
+fn bind_socket(path: &Path, mode: u32, gid: Option<gid_t>) -> Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        if !dir.exists() {
+            fs::create_dir_all(dir).context("Create socket directory")?;
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path).context("Create listener")?;
+    if let Some(gid) = gid {
+        std::os::unix::fs::chown(path, None, Some(gid)).context("Change socket group")?;
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// Reads are open to everyone connected, settings may be changed by root and socket group members
+fn check_writable(state: &DaemonState, peer: &Peer) -> Result<(), DaemonError> {
+    if state.may_write(peer) {
+        return Ok(());
+    }
+    warn!("Write by uid {} (pid {:?}) rejected", peer.uid, peer.pid);
+    Err(DaemonError::PermissionDenied(format!(
+        "only root and members of {} group may change settings",
+        state.config.socket.group
+    )))
+}
+
+/// Bring EC back to the settings, e.g. after EC reset them on reboot or resume.
+/// Fan mode is left to curves if there are any
+pub async fn restore_settings(state: &DaemonState) {
+    let settings = *state.settings.lock().await;
+    let has_curves = state.fan_curves.lock().await.is_some();
+    let mut ec = state.ec.lock().await;
+    if let Some(fan_mode) = settings.fan_mode.filter(|_| !has_curves) {
+        match FanMode::current_mode(&mut *ec).await {
+            Ok(current) if current == fan_mode => debug!("Fan mode is {fan_mode} as expected"),
+            Ok(current) => {
+                warn!("Fan mode drifted to {current}, restoring {fan_mode}");
+                if let Err(err) = ec.write_data(&fan_mode).await {
+                    error!("Failed to restore fan mode: {err:#}");
+                }
+            }
+            Err(err) => error!("Failed to read fan mode: {err:#}"),
+        }
+    }
+    if let Some(threshold) = settings.bat_threshold {
+        match BatThreshold::current_state(&mut *ec).await {
+            Ok(current) if *current == threshold => {
+                debug!("Battery threshold is {threshold} as expected")
+            }
+            Ok(current) => {
+                warn!(
+                    "Battery threshold drifted to {}, restoring {threshold}",
+                    *current
+                );
+                if let Err(err) = ec.write_data(&BatThreshold::new(threshold)).await {
+                    error!("Failed to restore battery threshold: {err:#}");
+                }
+            }
+            Err(err) => error!("Failed to read battery threshold: {err:#}"),
+        }
+    }
+}
+
+/// Remember settings applied by client to restore them later
+async fn update_settings(state: &DaemonState, update: impl FnOnce(&mut Settings)) {
+    let mut settings = state.settings.lock().await;
+    update(&mut settings);
+    if let Err(err) = settings.save(&state.config.state_dir) {
+        error!("Failed to save settings: {err:#}");
+    }
+}
+
+/// Apply fan curves (if any) according to current temperatures
+async fn run_fan_curves(state: Arc<DaemonState>, interval: Duration) {
+    let mut controller = CurveController::default();
+    // Mode written by the controller last time
+    let mut last_mode = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        // EC is locked before curves and kept until the write is done, like in `set_fan_mode`,
+        // so a fan mode set manually meanwhile isn't overwritten by the curves it disabled
+        let mut ec = state.ec.lock().await;
+        let Some(curves) = state.fan_curves.lock().await.clone() else {
+            controller.reset();
+            last_mode = None;
+            continue;
+        };
+        match apply_fan_curves(&mut ec, &curves, &mut controller, last_mode).await {
+            Ok(mode) => last_mode = Some(mode),
+            Err(err) => error!("Failed to apply fan curves: {err:#}"),
+        }
+    }
+}
+
+async fn apply_fan_curves(
+    ec: &mut EmbeddedController,
+    curves: &FanCurves,
+    controller: &mut CurveController,
+    last_mode: Option<FanMode>,
+) -> Result<FanMode> {
+    let cpu_temp = CpuTemp::current_state(ec).await?;
+    let gpu_temp = GpuTemp::current_state(ec).await?;
+    let current_mode = FanMode::current_mode(ec).await?;
+    if last_mode != Some(current_mode) {
+        // Fan mode was changed outside of the controller, start from scratch
+        controller.reset();
+    }
+    let fan_mode = controller.next_mode(curves, *cpu_temp, *gpu_temp);
+    if current_mode != fan_mode {
+        _ = ec.write_data(&fan_mode).await?;
+        debug!(
+            "Fan curves: CPU {}°C, GPU {}°C, fan mode set to {fan_mode}",
+            *cpu_temp, *gpu_temp
+        );
+    }
+    Ok(fan_mode)
+}
+
+/// Stop driving fans by curves, e.g. when fan mode is set manually
+async fn disable_fan_curves(state: &DaemonState) -> Result<(), DaemonError> {
+    let mut fan_curves = state.fan_curves.lock().await;
+    if fan_curves.take().is_some() {
+        FanCurves::save(&state.config.state_dir, None)
+            .map_err(|err| DaemonError::internal(&err))?;
+        info!("Fan curves disabled");
+        state.subscriptions.publish(DaemonEvent::FanCurves(None));
+    }
+    Ok(())
+}
+
+/// Set fan mode requested by client or power rule. Manual fan mode disables curves
+pub async fn set_fan_mode(
+    state: &DaemonState,
+    fan_mode: FanMode,
+) -> Result<WriteResult, DaemonError> {
+    if !fan_mode.is_valid() {
+        return Err(DaemonError::InvalidArgument(format!(
+            "fan mode {fan_mode} can't be set"
+        )));
+    }
+    // Fan curves don't get EC in between disabling and the write, see `run_fan_curves`
+    let mut ec = state.ec.lock().await;
+    disable_fan_curves(state).await?;
+    let res = write_result(ec.write_data(&fan_mode).await)?;
+    drop(ec);
+    if let WriteResult::Done = res {
+        info!("Fan mode set to {fan_mode}");
+        state.subscriptions.publish(DaemonEvent::FanMode(fan_mode));
+        update_settings(state, |settings| settings.fan_mode = Some(fan_mode)).await;
+    }
+    Ok(res)
+}
+
+pub async fn set_bat_threshold(
+    state: &DaemonState,
+    threshold: u8,
+) -> Result<WriteResult, DaemonError> {
+    if !(60..=100).contains(&threshold) {
+        return Err(DaemonError::InvalidArgument(
+            "battery threshold must be from 60 to 100".to_owned(),
+        ));
+    }
+    let res = write_result(
+        state
+            .ec
+            .lock()
+            .await
+            .write_data(&BatThreshold::new(threshold))
+            .await,
+    )?;
+    if let WriteResult::Done = res {
+        info!("Battery threshold set to {threshold}");
+        state
+            .subscriptions
+            .publish(DaemonEvent::BatThreshold(threshold));
+        update_settings(state, |settings| settings.bat_threshold = Some(threshold)).await;
+    }
+    Ok(res)
+}
+
+/// Apply settings of the power rule. The first failed write stops it.
+/// Fan mode is left to curves if there are any, unlike fan mode set by client
+pub async fn apply_power_rule(
+    state: &DaemonState,
+    rule: PowerRule,
+) -> Result<WriteResult, DaemonError> {
+    let has_curves = state.fan_curves.lock().await.is_some();
+    if let (Some(fan_mode), true) = (rule.fan_mode, has_curves) {
+        info!("Fan mode {fan_mode} of power rule is skipped, fans are driven by curves");
+    } else if let Some(fan_mode) = rule.fan_mode {
+        let res = set_fan_mode(state, fan_mode).await?;
+        if !matches!(res, WriteResult::Done) {
+            return Ok(res);
+        }
+    }
+    if let Some(threshold) = rule.bat_threshold {
+        return set_bat_threshold(state, threshold).await;
+    }
+    Ok(WriteResult::Done)
+}
+
+/// Failed write verification is reported as write result, so client sees what was rolled back
+fn write_result(res: Result<WriteResult>) -> Result<WriteResult, DaemonError> {
+    match res {
+        Ok(res) => Ok(res),
+        Err(err) => match err.downcast::<WriteFailure>() {
+            Ok(failure) => {
+                error!("{failure}");
+                Ok(WriteResult::Failed(failure))
+            }
+            Err(err) => Err(DaemonError::from_ec(&err)),
+        },
+    }
+}
+
+pub async fn handle_incoming(mut stream: FramedServer, state: Arc<DaemonState>) -> Result<()> {
+    let peer = Peer::from_stream(stream.get_ref())?;
     loop {
         let Some(req) = stream.next().await else {
             info!("Connection finished");
             return Ok(());
         };
-        let Ok(req) = req else {
-            bail!("Unknown daemon request");
+        let req = match req {
+            Ok(DaemonReq::Subscribe { interval_ms }) => {
+                return stream_events(&mut stream, &state, Duration::from_millis(interval_ms))
+                    .await;
+            }
+            Ok(req) => req,
+            Err(err) => {
+                warn!("Malformed request from uid {}: {err}", peer.uid);
+                let err = DaemonError::InvalidArgument(format!("malformed request ({err})"));
+                stream.send(DaemonResp::Error(err)).await?;
+                continue;
+            }
         };
-        match req {
-            DaemonReq::SetFanMode(fan_mode) => {
-                _ = ec.lock().await.write_data(&fan_mode).await?;
-                info!("Fan mode set to {fan_mode}");
-                stream
-                    .send(DaemonResp::WriteResult(WriteResult::Done))
-                    .await?;
-            }
-            DaemonReq::SetBatThreshold(bat_threshold) => {
-                if *bat_threshold < 60 || *bat_threshold > 100 {
-                    bail!("Unknown daemon request");
-                }
-                _ = ec.lock().await.write_data(&bat_threshold).await?;
-                info!("Battery threshold set to {}", *bat_threshold);
-                stream
-                    .send(DaemonResp::WriteResult(WriteResult::Done))
-                    .await?;
+        let resp = match handle_request(&state, req, &peer).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!("Request from uid {} failed: {err}", peer.uid);
+                DaemonResp::Error(err)
             }
-            DaemonReq::ReadValues(mut values) => {
-                ec.lock().await.read_data_inner(&mut values).await?;
-                debug!("Read data: {values:#?}");
-                stream.send(DaemonResp::ReadValues(values)).await?;
+        };
+        stream.send(resp).await?;
+    }
+}
+
+/// Registers beyond model profile may expose anything EC keeps, so only admins may read them
+fn check_readable(state: &DaemonState, values: &[RWData], peer: &Peer) -> Result<(), DaemonError> {
+    let Some(reg) = values
+        .iter()
+        .flat_map(RWData::regs)
+        .find(|reg| !state.readable_regs.contains(reg))
+    else {
+        return Ok(());
+    };
+    if peer.is_owner() || state.admin_gid.is_some_and(|gid| peer.in_group(gid)) {
+        return Ok(());
+    }
+    warn!(
+        "Read of register {reg:#04x} by uid {} (pid {:?}) rejected",
+        peer.uid, peer.pid
+    );
+    Err(DaemonError::PermissionDenied(format!(
+        "register {reg:#04x} isn't used by model profile, only root and members of admin group may read it"
+    )))
+}
+
+/// Every failure is returned to client, so connection stays open
+async fn handle_request(
+    state: &DaemonState,
+    req: DaemonReq,
+    peer: &Peer,
+) -> Result<DaemonResp, DaemonError> {
+    let resp = match req {
+        DaemonReq::SetFanMode(fan_mode) => {
+            check_writable(state, peer)?;
+            DaemonResp::WriteResult(set_fan_mode(state, fan_mode).await?)
+        }
+        DaemonReq::SetBatThreshold(bat_threshold) => {
+            check_writable(state, peer)?;
+            DaemonResp::WriteResult(set_bat_threshold(state, bat_threshold.value()).await?)
+        }
+        DaemonReq::ReadValues(mut values) => {
+            if !values.iter().all(RWData::is_valid) {
+                return Err(DaemonError::InvalidArgument(RWData::INVALID.to_owned()));
             }
+            check_readable(state, &values, peer)?;
+            state
+                .ec
+                .lock()
+                .await
+                .read_data_inner(&mut values)
+                .await
+                .map_err(|err| DaemonError::from_ec(&err))?;
+            debug!("Read data: {values:#?}");
+            DaemonResp::ReadValues(values)
         }
-    }
+        DaemonReq::SetFanCurves(curves) => {
+            check_writable(state, peer)?;
+            if let Some(curves) = &curves {
+                curves
+                    .check()
+                    .map_err(|err| DaemonError::InvalidArgument(format!("{err:#}")))?;
+            }
+            let mut fan_curves = state.fan_curves.lock().await;
+            FanCurves::save(&state.config.state_dir, curves.as_ref())
+                .map_err(|err| DaemonError::internal(&err))?;
+            match &curves {
+                Some(curves) => info!("Fan curves set to {curves}"),
+                None => info!("Fan curves disabled"),
+            }
+            *fan_curves = curves.clone();
+            state.subscriptions.publish(DaemonEvent::FanCurves(curves));
+            DaemonResp::WriteResult(WriteResult::Done)
+        }
+        DaemonReq::GetFanCurves => DaemonResp::FanCurves(state.fan_curves.lock().await.clone()),
+        DaemonReq::WriteRaw(data) => {
+            if !data.is_valid() {
+                return Err(DaemonError::InvalidArgument(RWData::INVALID.to_owned()));
+            }
+            // Socket is open to everyone, but raw writes may damage hardware
+            if !peer.is_owner() {
+                return Err(DaemonError::PermissionDenied(
+                    "raw writes are allowed to root only".to_owned(),
+                ));
+            }
+            let mut ec = state.ec.lock().await;
+            let prev = ec
+                .read_values(vec![data.to_read()])
+                .await
+                .map_err(|err| DaemonError::from_ec(&err))?;
+            let res = write_result(ec.write_data(&data).await)?;
+            if let (WriteResult::Done, Some(prev)) = (&res, prev.first()) {
+                warn!(
+                    "Raw write by uid {}: register {:#04x} changed from {} to {}",
+                    peer.uid,
+                    data.reg(),
+                    RawValue(*prev),
+                    RawValue(data.0)
+                );
+            }
+            DaemonResp::WriteResult(res)
+        }
+        DaemonReq::SetPowerRules(rules) => {
+            check_writable(state, peer)?;
+            rules
+                .check()
+                .map_err(|err| DaemonError::InvalidArgument(format!("{err:#}")))?;
+            rules
+                .save(&state.config.state_dir)
+                .map_err(|err| DaemonError::internal(&err))?;
+            *state.power_rules.lock().await = rules;
+            info!(
+                "Power rules set to AC: {}, battery: {}",
+                rules.ac, rules.battery
+            );
+            let source = PowerSource::current(&PowerSource::supply_dir()).unwrap_or_else(|err| {
+                warn!("Failed to detect power source: {err:#}");
+                None
+            });
+            match source {
+                Some(source) => {
+                    DaemonResp::WriteResult(apply_power_rule(state, *rules.rule(source)).await?)
+                }
+                None => DaemonResp::WriteResult(WriteResult::Done),
+            }
+        }
+        DaemonReq::GetPowerRules => DaemonResp::PowerRules(*state.power_rules.lock().await),
+        DaemonReq::Subscribe { .. } => unreachable!("Subscription takes over connection"),
+    };
+    Ok(resp)
 }
 
 #[cfg(feature = "self-packed")]
@@ -87,7 +585,11 @@ pub fn install_daemon() -> Result<()> {
     use std::{fs::File, io::Write, process::Command};
 
     _ = Command::new("systemctl")
-        .args(["stop", "gigacenter-daemon.service"])
+        .args([
+            "stop",
+            "gigacenter-daemon.service",
+            "gigacenter-daemon.socket",
+        ])
         .spawn()?
         .wait();
 
@@ -104,18 +606,78 @@ pub fn install_daemon() -> Result<()> {
         }
         info!("Binary successfully installed to /usr/local/bin");
     }
+    create_socket_group()?;
+    info!("Installing D-Bus and polkit policies");
+    fs::write(DBUS_POLICY_PATH, DBUS_POLICY).context("Install D-Bus policy")?;
+    fs::write(POLKIT_POLICY_PATH, POLKIT_POLICY).context("Install polkit policy")?;
     info!("Installing systemd service");
     let mut file = File::create("/etc/systemd/system/gigacenter-daemon.service")?;
     file.write_all(SYSTEMD_SERVICE)?;
+    let mut file = File::create("/etc/systemd/system/gigacenter-daemon.socket")?;
+    file.write_all(SYSTEMD_SOCKET)?;
+    _ = Command::new("systemctl")
+        .arg("daemon-reload")
+        .spawn()?
+        .wait()?;
     let res = Command::new("systemctl")
-        .args(["enable", "gigacenter-daemon.service", "--now"])
+        .args([
+            "enable",
+            "gigacenter-daemon.socket",
+            "gigacenter-daemon.service",
+            "--now",
+        ])
         .spawn()?
         .wait()?;
     if !res.success() {
-        bail!("Failed to install binary to /usr/local/bin")
+        bail!("Failed to enable systemd service")
     }
     info!("Systemd service successfully installed");
+    info!("Desktop power profiles are left to power-profiles-daemon. To switch fan modes from power menu, run `systemctl mask --now power-profiles-daemon` and set `enabled = true` in [power_profiles] section of {CONFIG_PATH}");
+
+    Ok(())
+}
+
+/// Create the group allowed to change settings and add the user running installer to it
+#[cfg(feature = "self-packed")]
+fn create_socket_group() -> Result<()> {
+    use super::peer::user_name;
+    use std::process::Command;
 
+    let group = match Config::load() {
+        Ok(config) => config.socket.group,
+        Err(err) => {
+            warn!("Failed to load config, using default socket group: {err:#}");
+            Config::default().socket.group
+        }
+    };
+    if group.is_empty() {
+        return Ok(());
+    }
+    if group_id(&group)?.is_none() {
+        info!("Creating group {group}");
+        let res = Command::new("groupadd")
+            .args(["--system", &group])
+            .spawn()?
+            .wait()?;
+        if !res.success() {
+            bail!("Failed to create group {group}")
+        }
+    }
+    let user = std::env::var("PKEXEC_UID")
+        .ok()
+        .and_then(|uid| user_name(uid.parse().ok()?))
+        .or_else(|| std::env::var("SUDO_USER").ok());
+    if let Some(user) = user.filter(|user| user != "root") {
+        let res = Command::new("usermod")
+            .args(["-aG", &group, &user])
+            .spawn()?
+            .wait()?;
+        if res.success() {
+            info!("User {user} added to group {group}, log in again to change settings");
+        } else {
+            warn!("Failed to add {user} to group {group}");
+        }
+    }
     Ok(())
 }
 
@@ -128,11 +690,22 @@ pub fn remove_daemon() -> Result<()> {
         .spawn()?
         .wait()?;
     _ = Command::new("systemctl")
-        .args(["disable", "gigacenter-daemon.service", "--now"])
+        .args([
+            "disable",
+            "gigacenter-daemon.service",
+            "gigacenter-daemon.socket",
+            "--now",
+        ])
         .spawn()?
         .wait()?;
     _ = Command::new("rm")
-        .args(["-f", "/etc/systemd/system/gigacenter-daemon.service"])
+        .args([
+            "-f",
+            "/etc/systemd/system/gigacenter-daemon.service",
+            "/etc/systemd/system/gigacenter-daemon.socket",
+            DBUS_POLICY_PATH,
+            POLKIT_POLICY_PATH,
+        ])
         .spawn()?
         .wait()?;
 
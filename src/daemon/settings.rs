@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rkyv::{rancor::Error as RkyvError, Archive, Deserialize, Serialize};
+
+use crate::fan_speed::FanMode;
+
+/// File in daemon state directory with the last settings applied by clients
+pub const SETTINGS_FILE: &str = "state";
+
+/// Settings the daemon keeps EC in. Restored at startup and after resume as EC may reset them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct Settings {
+    pub fan_mode: Option<FanMode>,
+    pub bat_threshold: Option<u8>,
+}
+
+impl Settings {
+    /// Load saved settings. Nothing is set if the daemon has never saved them
+    pub fn load(state_dir: &Path) -> Result<Self> {
+        let content = match std::fs::read(state_dir.join(SETTINGS_FILE)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err).context("Read state file"),
+        };
+        rkyv::from_bytes::<Self, RkyvError>(&content).context("Parse state file")
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(state_dir).context("Create state directory")?;
+        let bytes = rkyv::to_bytes::<RkyvError>(self)?;
+        std::fs::write(state_dir.join(SETTINGS_FILE), bytes).context("Write state file")?;
+        Ok(())
+    }
+
+    /// Saved settings take precedence, missing ones are taken from `defaults`
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            fan_mode: self.fan_mode.or(defaults.fan_mode),
+            bat_threshold: self.bat_threshold.or(defaults.bat_threshold),
+        }
+    }
+}
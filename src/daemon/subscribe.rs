@@ -0,0 +1,183 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use futures::{SinkExt as _, StreamExt as _};
+use log::{debug, error, info};
+use rkyv::{Archive, Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+};
+
+use crate::{fan_curve::FanCurves, fan_speed::FanMode, monitor::Monitor};
+
+use super::{
+    codec::{DaemonResp, FramedServer},
+    server::DaemonState,
+};
+
+/// Monitor is read at most this often, whatever subscribers ask for
+pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+/// Events kept for slow subscribers before they start to miss them
+const EVENTS_CAPACITY: usize = 16;
+
+/// Pushed by daemon to subscribed clients
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+pub enum DaemonEvent {
+    /// Machine state polled by daemon
+    Monitor(Monitor),
+    /// Settings changed by some client or power rule
+    FanMode(FanMode),
+    BatThreshold(u8),
+    FanCurves(Option<FanCurves>),
+}
+
+/// Fan-out of daemon events. Monitor is polled once for all subscribers
+#[derive(Debug)]
+pub struct Subscriptions {
+    events: broadcast::Sender<DaemonEvent>,
+    /// Intervals requested by active subscribers
+    intervals: Mutex<Vec<Duration>>,
+    changed: Notify,
+}
+
+/// Receiver of events, unregistered on drop
+pub struct Subscription<'a> {
+    subscriptions: &'a Subscriptions,
+    /// `None` if monitor polling wasn't asked for
+    interval: Option<Duration>,
+    events: broadcast::Receiver<DaemonEvent>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            intervals: Mutex::new(Vec::new()),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Send event to every subscriber. Nothing happens if there are none
+    pub fn publish(&self, event: DaemonEvent) {
+        _ = self.events.send(event);
+    }
+
+    /// Receive events with monitor polled at least every `interval`
+    pub fn subscribe(&self, interval: Duration) -> Subscription<'_> {
+        self.intervals.lock().unwrap().push(interval);
+        self.changed.notify_one();
+        Subscription {
+            subscriptions: self,
+            interval: Some(interval),
+            events: self.events.subscribe(),
+        }
+    }
+
+    /// Receive events without polling monitor. Monitor polled for other subscribers still comes
+    pub fn subscribe_events(&self) -> Subscription<'_> {
+        Subscription {
+            subscriptions: self,
+            interval: None,
+            events: self.events.subscribe(),
+        }
+    }
+
+    /// Monitor polling interval, the shortest one requested. `None` if nobody is subscribed
+    fn interval(&self) -> Option<Duration> {
+        self.intervals.lock().unwrap().iter().min().copied()
+    }
+}
+
+impl Subscription<'_> {
+    /// Next event. Events missed by slow subscriber are skipped
+    pub async fn next(&mut self) -> Option<DaemonEvent> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(missed)) => debug!("Subscriber missed {missed} events"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let mut intervals = self.subscriptions.intervals.lock().unwrap();
+        if let Some(idx) = intervals.iter().position(|val| *val == interval) {
+            _ = intervals.swap_remove(idx);
+        }
+    }
+}
+
+/// Poll monitor for all subscribers. Sleeps while there are none
+pub async fn run_monitor(state: Arc<DaemonState>) {
+    let mut last_poll = Instant::now();
+    loop {
+        let Some(interval) = state.subscriptions.interval() else {
+            state.subscriptions.changed.notified().await;
+            continue;
+        };
+        // New subscriber may need a shorter interval than the one being waited for
+        tokio::select! {
+            () = tokio::time::sleep_until((last_poll + interval).into()) => {}
+            () = state.subscriptions.changed.notified() => continue,
+        }
+        last_poll = Instant::now();
+        let monitor = Monitor::try_new(&mut *state.ec.lock().await).await;
+        match monitor {
+            Ok(monitor) => state.subscriptions.publish(DaemonEvent::Monitor(monitor)),
+            Err(err) => error!("Failed to read monitor: {err:#}"),
+        }
+    }
+}
+
+/// Push events to subscribed client until it disconnects
+pub async fn stream_events(
+    stream: &mut FramedServer,
+    state: &DaemonState,
+    interval: Duration,
+) -> Result<()> {
+    let interval = interval.max(MIN_INTERVAL);
+    let mut subscription = state.subscriptions.subscribe(interval);
+    info!("Client subscribed with interval {}ms", interval.as_millis());
+    // The first snapshot is sent right away, so client doesn't wait for the next poll
+    let monitor = Monitor::try_new(&mut *state.ec.lock().await).await?;
+    stream
+        .send(DaemonResp::Event(DaemonEvent::Monitor(monitor)))
+        .await?;
+    let mut last_monitor = Instant::now();
+    loop {
+        let event = tokio::select! {
+            event = subscription.next() => event,
+            req = stream.next() => {
+                if req.is_none() {
+                    info!("Subscriber disconnected");
+                    return Ok(());
+                }
+                bail!("Subscribed client must not send requests");
+            }
+        };
+        let Some(event) = event else {
+            return Ok(());
+        };
+        if let DaemonEvent::Monitor(_) = event {
+            // Poll interval is the shortest one, so skip snapshots this subscriber doesn't need.
+            // Some slack keeps timer jitter from skipping every other snapshot
+            if last_monitor.elapsed() + interval / 10 < interval {
+                continue;
+            }
+            last_monitor = Instant::now();
+        }
+        stream.send(DaemonResp::Event(event)).await?;
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    env,
+    os::{
+        fd::{FromRawFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use tokio::net::UnixListener;
+
+use crate::temp::CpuTemp;
+
+use super::server::DaemonState;
+
+/// The first descriptor passed by systemd, see `sd_listen_fds(3)`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Listening socket passed by systemd `.socket` unit. `None` if the daemon isn't socket activated.
+/// Clears `LISTEN_*` environment, so it must be called before any other thread is started
+pub fn listen_fds() -> Result<Option<UnixListener>> {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<u32>().ok())
+        .unwrap_or(0);
+    // Descriptors are meant for this process only, like with `sd_listen_fds(1)`
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if !for_us || count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("{count} sockets passed by systemd, only the first one is used");
+    }
+    _ = unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) };
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .set_nonblocking(true)
+        .context("Set passed socket nonblocking")?;
+    let listener = UnixListener::from_std(listener).context("Use socket passed by systemd")?;
+    Ok(Some(listener))
+}
+
+/// Send state to service manager, e.g. `READY=1`. Does nothing outside of `Type=notify` service
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let res = (|| {
+        let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    })();
+    if let Err(err) = res {
+        warn!("Failed to notify systemd with {state}: {err}");
+    }
+}
+
+/// Watchdog timeout set by `WatchdogSec=`. `None` if watchdog is disabled
+pub fn watchdog_timeout() -> Option<Duration> {
+    let for_us = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_none_or(|pid| pid == std::process::id());
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (for_us && usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Ping watchdog while EC responds. If EC hangs, pings stop and systemd restarts the daemon.
+/// Probe and pause take a third of timeout each, so a slow probe still leaves time for the next ping
+pub async fn run_watchdog(state: Arc<DaemonState>, timeout: Duration) {
+    let interval = timeout / 3;
+    debug!("Pinging watchdog every {interval:?}");
+    loop {
+        let probe = async { CpuTemp::current_state(&mut *state.ec.lock().await).await };
+        match tokio::time::timeout(interval, probe).await {
+            Ok(Ok(_)) => notify("WATCHDOG=1"),
+            Ok(Err(err)) => warn!("EC is unresponsive, watchdog isn't pinged: {err:#}"),
+            Err(_) => warn!("EC didn't respond in {interval:?}, watchdog isn't pinged"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
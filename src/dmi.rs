@@ -0,0 +1,39 @@
+use std::fmt::Display;
+
+/// Directory where kernel exposes DMI (SMBIOS) identification
+const DMI_ID_PATH: &str = "/sys/class/dmi/id";
+
+/// Laptop identification read from DMI. Missing fields are empty
+#[derive(Debug, Clone, Default)]
+pub struct Dmi {
+    pub sys_vendor: String,
+    pub product_name: String,
+    pub board_name: String,
+    pub bios_version: String,
+}
+
+impl Dmi {
+    pub fn read() -> Self {
+        let read_field = |name: &str| {
+            std::fs::read_to_string(format!("{DMI_ID_PATH}/{name}"))
+                .map(|value| value.trim().to_owned())
+                .unwrap_or_default()
+        };
+        Self {
+            sys_vendor: read_field("sys_vendor"),
+            product_name: read_field("product_name"),
+            board_name: read_field("board_name"),
+            bios_version: read_field("bios_version"),
+        }
+    }
+}
+
+impl Display for Dmi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} (board: {}, BIOS: {})",
+            self.sys_vendor, self.product_name, self.board_name, self.bios_version
+        )
+    }
+}
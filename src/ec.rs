@@ -1,22 +1,29 @@
+#![expect(
+    forbidden_lint_groups,
+    reason = "serde derive allows `unused_extern_crates` forbidden by `rust_2018_idioms`"
+)]
+
 use rkyv::{Archive, Deserialize, Serialize};
 use std::{
+    collections::{btree_map::Entry, BTreeMap},
     fmt::Display,
-    io::SeekFrom,
     num::{IntErrorKind, ParseIntError},
-    process::{Command, Stdio},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    fs::{File, OpenOptions},
-    io::{AsyncReadExt, AsyncSeekExt as _, AsyncWriteExt},
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
 };
 
 use anyhow::{bail, Context, Error, Result};
 use libc::geteuid;
+use log::error;
 
 use crate::{
+    backend::{Backend, EcAccess, EcBackend},
     deref,
-    traits::{ECHandler, ReadEC, WriteEC, WriteResult},
+    profile::is_writable,
+    traits::{ECHandler, FailedReg, ReadEC, WriteEC, WriteFailure, WriteResult},
 };
 
 /// Register (addr) of embedded controller
@@ -36,18 +43,13 @@ impl Display for BitState {
 }
 
 /// Represent the position of bit in u8
-#[derive(Clone, Copy, Debug, Archive, Serialize, Deserialize)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize, serde::Deserialize,
+)]
+#[serde(try_from = "u8")]
 pub struct BitPos(u8);
 
 impl BitPos {
-    /// May panic if bit_num > 7
-    pub const fn new(bit_num: u8) -> Self {
-        if bit_num > 7 {
-            panic!("Bit number must be from 0 to 7");
-        }
-        Self(bit_num)
-    }
-
     pub fn try_new(bit_num: u8) -> Result<Self, Error> {
         if bit_num > 7 {
             bail!("Bit number must be from 0 to 7")
@@ -56,10 +58,46 @@ impl BitPos {
     }
 }
 
+impl TryFrom<u8> for BitPos {
+    type Error = Error;
+
+    fn try_from(bit_num: u8) -> Result<Self, Self::Error> {
+        Self::try_new(bit_num)
+    }
+}
+
 deref!(BitPos(u8));
 
+/// Byte order of multi-byte EC values
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Archive, Serialize, Deserialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    /// Value of two consecutive registers (`reg`, `reg + 1`)
+    pub fn decode(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    /// Bytes of two consecutive registers (`reg`, `reg + 1`) holding the value
+    pub fn encode(self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Big => value.to_be_bytes(),
+            ByteOrder::Little => value.to_le_bytes(),
+        }
+    }
+}
+
 /// Represent bit or byte to be written/read to/from specified register
-#[derive(Debug, Archive, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Archive, Serialize, Deserialize)]
 pub enum RWData {
     U1 {
         reg: Reg,
@@ -70,47 +108,75 @@ pub enum RWData {
         reg: u8,
         value: u8,
     },
+    /// Value of `reg` and `reg + 1`
     U16 {
         reg: u8,
         value: u16,
+        byte_order: ByteOrder,
     },
 }
 
+impl RWData {
+    /// Why data isn't valid, see [`RWData::is_valid`]
+    pub const INVALID: &str =
+        "bit number must be from 0 to 7 and 16-bit value must start below register 0xff";
+
+    /// Bit position and 16-bit register must be checked as they may come from client.
+    /// 16-bit value at the last register would wrap to register 0x00
+    pub fn is_valid(&self) -> bool {
+        match self {
+            RWData::U1 { pos, .. } => **pos <= 7,
+            RWData::U8 { .. } => true,
+            RWData::U16 { reg, .. } => *reg < Reg::MAX,
+        }
+    }
+
+    /// Registers touched by the data
+    pub fn regs(&self) -> Vec<Reg> {
+        match *self {
+            RWData::U1 { reg, .. } | RWData::U8 { reg, .. } => vec![reg],
+            RWData::U16 { reg, .. } => std::iter::once(reg).chain(reg.checked_add(1)).collect(),
+        }
+    }
+}
+
+/// Register of the second byte of 16-bit value at `reg`
+fn second_reg(reg: Reg) -> Result<Reg> {
+    reg.checked_add(1)
+        .context("16-bit value can't start at the last register 0xff")
+}
+
+/// Size of EC register space
+pub const EC_REGS: usize = 256;
+
 pub const WRITE_TIMEOUT_MS: u16 = 2500;
 const WRITE_TIMEOUT_PATH: &str = "/tmp/last-write-ec";
 
 /// Main struct to read/write data to/from EC
 #[derive(Debug)]
 pub struct EmbeddedController {
-    ec_file: File,
+    backend: Backend,
+    /// Writes to the real EC are throttled by [`WRITE_TIMEOUT_MS`], simulated one has nothing to protect
+    throttled: bool,
 }
 
 impl EmbeddedController {
-    pub async fn new() -> Result<Self> {
+    pub async fn new(access: &EcAccess) -> Result<Self> {
         let euid = unsafe { geteuid() };
-        if euid != 0 {
+        if euid != 0 && !access.is_simulated() {
             bail!("You must run this program as root")
         }
-        _ = Command::new("modprobe")
-            .args(["ec_sys", "write_support=1"])
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?
-            .wait()?;
-        let ec_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open("/sys/kernel/debug/ec/ec0/io")
-            .await?;
-        Ok(Self { ec_file })
+        let backend = Backend::open(access).await?;
+        Ok(Self {
+            backend,
+            throttled: !access.is_simulated(),
+        })
     }
 
     async fn read_u1(&mut self, reg: Reg, bit_num: BitPos) -> Result<BitState> {
-        _ = self.ec_file.seek(SeekFrom::Start(reg as u64)).await?;
-        let mut current_value = [0];
-        _ = self.ec_file.read_exact(&mut current_value).await?;
+        let current_value = self.read_u8(reg).await?;
         let byte_musk: u8 = 1 << bit_num.0;
-        let res = current_value[0] & byte_musk;
+        let res = current_value & byte_musk;
         if res == 0 {
             Ok(BitState::Disabled)
         } else {
@@ -118,18 +184,14 @@ impl EmbeddedController {
         }
     }
 
-    async fn read_u8(&mut self, reg: Reg) -> Result<u8> {
-        _ = self.ec_file.seek(SeekFrom::Start(reg as u64)).await?;
-        let mut current_value = [0];
-        _ = self.ec_file.read_exact(&mut current_value).await?;
-        Ok(current_value[0])
+    pub async fn read_u8(&mut self, reg: Reg) -> Result<u8> {
+        self.backend.read_byte(reg).await
     }
 
-    pub async fn read_u16(&mut self, reg: Reg) -> Result<u16> {
-        _ = self.ec_file.seek(SeekFrom::Start(reg as u64)).await?;
-        let mut current_value = [0, 0];
-        _ = self.ec_file.read_exact(&mut current_value).await?;
-        Ok(u16::from_be_bytes(current_value))
+    pub async fn read_u16(&mut self, reg: Reg, byte_order: ByteOrder) -> Result<u16> {
+        let first = self.read_u8(reg).await?;
+        let second = self.read_u8(second_reg(reg)?).await?;
+        Ok(byte_order.decode([first, second]))
     }
 
     async fn write_u1(&mut self, reg: Reg, bit_num: BitPos, value: BitState) -> Result<()> {
@@ -147,9 +209,107 @@ impl EmbeddedController {
     }
 
     async fn write_u8(&mut self, reg: Reg, value: u8) -> Result<()> {
-        _ = self.ec_file.seek(SeekFrom::Start(reg as u64)).await?;
-        self.ec_file.write_all(&[value]).await?;
-        Ok(())
+        self.backend.write_byte(reg, value).await
+    }
+
+    async fn write_u16(&mut self, reg: Reg, value: u16, byte_order: ByteOrder) -> Result<()> {
+        let second_reg = second_reg(reg)?;
+        let [first, second] = byte_order.encode(value);
+        self.write_u8(reg, first).await?;
+        self.write_u8(second_reg, second).await
+    }
+
+    /// Write batch, read written bits back and restore them if anything didn't stick
+    async fn write_verified(&mut self, ops: &[RWData]) -> Result<()> {
+        // Snapshot before writing anything, so the batch can be rolled back
+        let mut original = BTreeMap::new();
+        for reg in ops.iter().flat_map(RWData::regs) {
+            if let Entry::Vacant(entry) = original.entry(reg) {
+                _ = entry.insert(
+                    self.read_u8(reg)
+                        .await
+                        .with_context(|| format!("Snapshot register {reg:#04x}"))?,
+                );
+            }
+        }
+
+        // Written bits (mask) and their values for every register
+        let mut expected = BTreeMap::<Reg, (u8, u8)>::new();
+        let mut error = None;
+        for op in ops {
+            let res = match *op {
+                RWData::U1 { reg, pos, state } => {
+                    let (mask, value) = expected.entry(reg).or_default();
+                    *mask |= 1 << *pos;
+                    match state {
+                        BitState::Disabled => *value &= !(1 << *pos),
+                        BitState::Enabled => *value |= 1 << *pos,
+                    }
+                    self.write_u1(reg, pos, state).await
+                }
+                RWData::U8 { reg, value } => {
+                    _ = expected.insert(reg, (u8::MAX, value));
+                    self.write_u8(reg, value).await
+                }
+                RWData::U16 {
+                    reg,
+                    value,
+                    byte_order,
+                } => {
+                    let [first, second] = byte_order.encode(value);
+                    _ = expected.insert(reg, (u8::MAX, first));
+                    if let Some(second_reg) = reg.checked_add(1) {
+                        _ = expected.insert(second_reg, (u8::MAX, second));
+                    }
+                    self.write_u16(reg, value, byte_order).await
+                }
+            };
+            if let Err(err) = res {
+                error = Some(err);
+                break;
+            }
+        }
+
+        let mut failed = vec![];
+        for (&reg, &(mask, value)) in &expected {
+            let actual = if error.is_some() {
+                None
+            } else {
+                self.read_u8(reg).await.ok()
+            };
+            if actual.is_none_or(|actual| (actual ^ value) & mask != 0) {
+                failed.push(FailedReg {
+                    reg,
+                    mask,
+                    expected: value,
+                    actual,
+                });
+            }
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        // Only written bits are restored, the rest of register may be changed by EC meanwhile
+        let mut rolled_back = true;
+        for (&reg, &(mask, _)) in &expected {
+            let res = async {
+                let current = self.read_u8(reg).await?;
+                self.write_u8(reg, (current & !mask) | (original[&reg] & mask))
+                    .await
+            }
+            .await;
+            if let Err(err) = res {
+                error!("Failed to roll back register {reg:#04x}: {err:#}");
+                rolled_back = false;
+            }
+        }
+        Err(WriteFailure {
+            failed,
+            rolled_back,
+            error: error.map(|err| format!("{err:#}")),
+        }
+        .into())
     }
 
     #[inline]
@@ -162,14 +322,47 @@ impl EmbeddedController {
                 RWData::U8 { reg, value } => {
                     *value = self.read_u8(*reg).await?;
                 }
-                RWData::U16 { reg, value } => {
-                    *value = self.read_u16(*reg).await?;
+                RWData::U16 {
+                    reg,
+                    value,
+                    byte_order,
+                } => {
+                    *value = self.read_u16(*reg, *byte_order).await?;
                 }
             }
         }
         Ok(())
     }
 
+    /// Sleep until [`WRITE_TIMEOUT_MS`] passed since the last write
+    async fn wait_write_timeout(&self) {
+        let last_write_time = self.duration_since_last_write_ms().await;
+        let timeout = match last_write_time {
+            Ok(duration) => {
+                if duration as u16 > WRITE_TIMEOUT_MS {
+                    Duration::from_millis(0)
+                } else {
+                    Duration::from_millis((WRITE_TIMEOUT_MS as u64).saturating_sub(duration))
+                }
+            }
+            Err(err) => {
+                let root = err.root_cause();
+                let timeout = root
+                    .downcast_ref::<ParseIntError>()
+                    .and_then(|err| {
+                        // If IntErrorKind::Empty, then no write was done yet
+                        if *err.kind() == IntErrorKind::Empty {
+                            return Some(Duration::from_millis(0));
+                        }
+                        None
+                    })
+                    .unwrap_or_else(|| Duration::from_millis(WRITE_TIMEOUT_MS as u64));
+                timeout
+            }
+        };
+        tokio::time::sleep(timeout).await;
+    }
+
     async fn duration_since_last_write_ms(&self) -> Result<u64> {
         let mut file = OpenOptions::new()
             .create(true)
@@ -207,51 +400,120 @@ impl EmbeddedController {
     }
 }
 
+/// Writes are refused as the model profile is just a guess
+#[derive(Debug, Clone, Copy)]
+pub struct ReadOnlyModel;
+
+impl Display for ReadOnlyModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Writing to EC of unknown laptop model is refused. Use `--force-model` to override"
+        )
+    }
+}
+
+impl std::error::Error for ReadOnlyModel {}
+
 impl ECHandler for EmbeddedController {
     async fn write_data(&mut self, data: &impl WriteEC) -> Result<WriteResult> {
-        let last_write_time = self.duration_since_last_write_ms().await;
-        let timeout = match last_write_time {
-            Ok(duration) => {
-                if duration as u16 > WRITE_TIMEOUT_MS {
-                    Duration::from_millis(0)
-                } else {
-                    Duration::from_millis((WRITE_TIMEOUT_MS as u64).saturating_sub(duration))
-                }
-            }
-            Err(err) => {
-                let root = err.root_cause();
-                let timeout = root
-                    .downcast_ref::<ParseIntError>()
-                    .and_then(|err| {
-                        // If IntErrorKind::Empty, then no write was done yet
-                        if *err.kind() == IntErrorKind::Empty {
-                            return Some(Duration::from_millis(0));
-                        }
-                        None
-                    })
-                    .unwrap_or_else(|| Duration::from_millis(WRITE_TIMEOUT_MS as u64));
-                timeout
-            }
-        };
-        tokio::time::sleep(timeout).await;
-        let ops = data.data_to_write();
-        for op in ops {
-            match op {
-                RWData::U1 { reg, pos, state } => self.write_u1(reg, pos, state).await?,
-                RWData::U8 { reg, value } => self.write_u8(reg, value).await?,
-                // No need to write u16 data for now
-                RWData::U16 { .. } => {}
-            }
+        if !is_writable() {
+            return Err(ReadOnlyModel.into());
         }
-        if let Err(err) = self.set_last_write_time().await {
-            eprintln!("Failed tp set_last_write_time {err:?}");
-        };
+        if self.throttled {
+            self.wait_write_timeout().await;
+        }
+        let res = self.write_verified(&data.data_to_write()).await;
+        if self.throttled {
+            if let Err(err) = self.set_last_write_time().await {
+                eprintln!("Failed tp set_last_write_time {err:?}");
+            };
+        }
+        res?;
         Ok(WriteResult::Done)
     }
 
     async fn read_data<T: ReadEC>(&mut self) -> Result<Vec<RWData>> {
-        let mut ops = T::data_to_read();
-        self.read_data_inner(&mut ops).await?;
-        Ok(ops)
+        self.read_values(T::data_to_read()).await
+    }
+
+    async fn read_values(&mut self, mut data: Vec<RWData>) -> Result<Vec<RWData>> {
+        self.read_data_inner(&mut data).await?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn simulated(
+        name: &str,
+        regs: &[(Reg, u8)],
+        stuck_bits: &[(Reg, u8)],
+    ) -> EmbeddedController {
+        let path =
+            std::env::temp_dir().join(format!("gigacenter-{name}-{}.img", std::process::id()));
+        let mut image = vec![0; EC_REGS];
+        for (reg, value) in regs {
+            image[*reg as usize] = *value;
+        }
+        std::fs::write(&path, image).unwrap();
+        let mut ec = EmbeddedController::new(&EcAccess::Image(path))
+            .await
+            .unwrap();
+        let Backend::SimulatedEc(sim) = &mut ec.backend else {
+            unreachable!("Image is opened as simulated EC");
+        };
+        sim.stuck_bits.extend(stuck_bits.iter().copied());
+        ec
+    }
+
+    #[tokio::test]
+    async fn failed_write_is_rolled_back() {
+        // Bit 1 of 0x10 can't be set
+        let mut ec = simulated("rollback", &[(0x10, 0b01), (0x11, 0x20)], &[(0x10, 0b10)]).await;
+        let batch = [
+            RWData::U1 {
+                reg: 0x10,
+                pos: BitPos(1),
+                state: BitState::Enabled,
+            },
+            RWData::U8 {
+                reg: 0x11,
+                value: 0x55,
+            },
+            RWData::U1 {
+                reg: 0x10,
+                pos: BitPos(0),
+                state: BitState::Disabled,
+            },
+        ];
+
+        let err = ec.write_verified(&batch).await.unwrap_err();
+        let failure = err.downcast::<WriteFailure>().unwrap();
+        assert!(failure.rolled_back);
+        assert_eq!(failure.error, None);
+        assert_eq!(failure.failed.len(), 1);
+        let failed = &failure.failed[0];
+        assert_eq!(
+            (failed.reg, failed.mask, failed.expected, failed.actual),
+            (0x10, 0b11, 0b10, Some(0b00))
+        );
+        // Registers are back to the state before the batch, including the ones written fine
+        assert_eq!(ec.read_u8(0x10).await.unwrap(), 0b01);
+        assert_eq!(ec.read_u8(0x11).await.unwrap(), 0x20);
+    }
+
+    #[tokio::test]
+    async fn verified_write_succeeds() {
+        let mut ec = simulated("verified", &[], &[]).await;
+        let batch = [RWData::U16 {
+            reg: 0x20,
+            value: 0x1234,
+            byte_order: ByteOrder::Big,
+        }];
+        ec.write_verified(&batch).await.unwrap();
+        assert_eq!(ec.read_u16(0x20, ByteOrder::Big).await.unwrap(), 0x1234);
     }
 }
@@ -0,0 +1,238 @@
+use std::{collections::HashSet, fmt::Display, io::IsTerminal, path::Path, time::Duration};
+
+use anstyle::{AnsiColor, Style};
+use anyhow::{bail, Context, Result};
+
+use crate::{EmbeddedController, Reg, EC_REGS};
+
+/// Registers per row of hex table
+const ROW_LEN: usize = 16;
+
+/// Style of registers changed since the previous snapshot
+const CHANGED: Style = Style::new()
+    .bold()
+    .fg_color(Some(anstyle::Color::Ansi(AnsiColor::Red)));
+/// Style of registers changed at least once during `--watch`
+const CHANGED_BEFORE: Style = Style::new().fg_color(Some(anstyle::Color::Ansi(AnsiColor::Yellow)));
+
+/// Snapshot of all EC registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcDump([u8; EC_REGS]);
+
+/// Register having different values in two dumps
+#[derive(Debug, Clone, Copy)]
+pub struct RegDiff {
+    pub reg: Reg,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl EcDump {
+    pub async fn read(ec: &mut EmbeddedController) -> Result<Self> {
+        let mut regs = [0; EC_REGS];
+        for (reg, value) in regs.iter_mut().enumerate() {
+            *value = ec
+                .read_u8(reg as Reg)
+                .await
+                .with_context(|| format!("Read register {reg:#04x}"))?;
+        }
+        Ok(Self(regs))
+    }
+
+    /// Load dump saved by `ec dump --output` or raw 256-byte image (e.g. copy of `ec_sys` io file)
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            std::fs::read(path).with_context(|| format!("Read EC dump {}", path.display()))?;
+        if content.len() == EC_REGS {
+            // SAFETY: length is checked above
+            return Ok(Self(content.try_into().unwrap()));
+        }
+        let text = String::from_utf8(content).with_context(|| {
+            format!(
+                "EC dump {} is neither hex table nor raw image",
+                path.display()
+            )
+        })?;
+        text.parse()
+            .with_context(|| format!("Parse EC dump {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_string())
+            .with_context(|| format!("Write EC dump {}", path.display()))
+    }
+
+    pub fn diff(&self, new: &Self) -> Vec<RegDiff> {
+        self.0
+            .iter()
+            .zip(new.0.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(reg, (old, new))| RegDiff {
+                reg: reg as Reg,
+                old: *old,
+                new: *new,
+            })
+            .collect()
+    }
+
+    /// Hex table with registers styled by `highlight`
+    pub fn table(&self, highlight: impl Fn(Reg) -> Option<Style>) -> String {
+        let mut table = String::from("    ");
+        for col in 0..ROW_LEN {
+            table.push_str(&format!(" {col:02X}"));
+        }
+        for (row, values) in self.0.chunks(ROW_LEN).enumerate() {
+            table.push_str(&format!("\n{:02X}: ", row * ROW_LEN));
+            for (col, value) in values.iter().enumerate() {
+                match highlight((row * ROW_LEN + col) as Reg) {
+                    Some(style) => table.push_str(&format!(" {style}{value:02X}{style:#}")),
+                    None => table.push_str(&format!(" {value:02X}")),
+                }
+            }
+        }
+        table
+    }
+}
+
+impl Display for EcDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.table(|_| None))
+    }
+}
+
+impl std::str::FromStr for EcDump {
+    type Err = anyhow::Error;
+
+    /// Parse hex table printed by [`EcDump::table`]. Lines without `:` (e.g. header) are skipped
+    fn from_str(s: &str) -> Result<Self> {
+        let mut regs = [0; EC_REGS];
+        let mut filled = [false; EC_REGS / ROW_LEN];
+        for line in s.lines() {
+            let Some((offset, values)) = line.split_once(':') else {
+                continue;
+            };
+            let offset = usize::from_str_radix(offset.trim(), 16)
+                .with_context(|| format!("Parse row offset `{}`", offset.trim()))?;
+            if offset % ROW_LEN != 0 || offset >= EC_REGS {
+                bail!(
+                    "Row offset {offset:#04x} must be multiple of {ROW_LEN:#x} below {EC_REGS:#x}"
+                )
+            }
+            let values = values
+                .split_whitespace()
+                .map(|value| {
+                    u8::from_str_radix(value, 16).with_context(|| format!("Parse value `{value}`"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if values.len() != ROW_LEN {
+                bail!("Row {offset:#04x} must contain {ROW_LEN} values")
+            }
+            regs[offset..offset + ROW_LEN].copy_from_slice(&values);
+            filled[offset / ROW_LEN] = true;
+        }
+        if let Some(row) = filled.iter().position(|filled| !filled) {
+            bail!("Row {:#04x} is missing", row * ROW_LEN)
+        }
+        Ok(Self(regs))
+    }
+}
+
+impl Display for RegDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#04x}: {:#04x} -> {:#04x}",
+            self.reg, self.old, self.new
+        )?;
+        let changed_bits = self.old ^ self.new;
+        let bits = (0..8)
+            .filter(|bit| changed_bits & (1 << bit) != 0)
+            .map(|bit| {
+                format!(
+                    "bit {bit}: {} -> {}",
+                    self.old >> bit & 1,
+                    self.new >> bit & 1
+                )
+            })
+            .collect::<Vec<_>>();
+        write!(f, " ({})", bits.join(", "))
+    }
+}
+
+/// Colors only make sense in terminal, files get plain text
+fn use_colors() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+/// Print dump and optionally save it to `output`
+pub async fn dump(ec: &mut EmbeddedController, output: Option<&Path>) -> Result<()> {
+    let dump = EcDump::read(ec).await?;
+    println!("{dump}");
+    if let Some(output) = output {
+        dump.save(output)?;
+    }
+    Ok(())
+}
+
+/// Print changed registers of two dumps along with the new dump where they are highlighted
+pub fn diff(old: &Path, new: &Path) -> Result<()> {
+    let (old, new) = (EcDump::load(old)?, EcDump::load(new)?);
+    let changes = old.diff(&new);
+    if changes.is_empty() {
+        println!("Dumps are identical");
+        return Ok(());
+    }
+    let changed = changes.iter().map(|diff| diff.reg).collect::<HashSet<_>>();
+    let colors = use_colors();
+    println!(
+        "{}\n",
+        new.table(|reg| (colors && changed.contains(&reg)).then_some(CHANGED))
+    );
+    for change in changes {
+        println!("{change}");
+    }
+    Ok(())
+}
+
+/// Redraw dump every `interval` highlighting registers changed since the previous snapshot
+/// and ones changed at least once. Runs until interrupted. Snapshots are appended as plain text
+/// if stdout isn't a terminal, e.g. redirected to file
+pub async fn watch(ec: &mut EmbeddedController, interval: Duration) -> Result<()> {
+    let mut prev = EcDump::read(ec).await?;
+    let mut changed_before = HashSet::new();
+    let terminal = std::io::stdout().is_terminal();
+    let colors = use_colors();
+    loop {
+        let dump = EcDump::read(ec).await?;
+        let diffs = prev.diff(&dump);
+        let changed = diffs.iter().map(|diff| diff.reg).collect::<HashSet<_>>();
+        if terminal {
+            // Clear screen and move cursor home
+            print!("\x1b[2J\x1b[H");
+        }
+        println!(
+            "{}",
+            dump.table(|reg| {
+                if !colors {
+                    None
+                } else if changed.contains(&reg) {
+                    Some(CHANGED)
+                } else if changed_before.contains(&reg) {
+                    Some(CHANGED_BEFORE)
+                } else {
+                    None
+                }
+            })
+        );
+        for diff in &diffs {
+            println!("{diff}");
+        }
+        if !terminal {
+            println!();
+        }
+        changed_before.extend(changed);
+        prev = dump;
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use rkyv::{rancor::Error as RkyvError, Archive, Deserialize, Serialize};
+
+use crate::{fan_speed::FanMode, WRITE_TIMEOUT_MS};
+
+/// File in daemon state directory with the last applied fan curves
+pub const FAN_CURVE_FILE: &str = "fan-curve";
+
+/// Point of fan curve: at `temp` (°C) fan spins at `speed` (in percent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub temp: u8,
+    pub speed: u8,
+}
+
+/// Temperature based fan curve. Speed between points is linearly interpolated,
+/// below the first point and above the last one speed of the nearest point is used
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct FanCurve {
+    points: Vec<CurvePoint>,
+}
+
+impl FanCurve {
+    pub fn try_new(points: Vec<CurvePoint>) -> Result<Self> {
+        let curve = Self { points };
+        curve.check()?;
+        Ok(curve)
+    }
+
+    /// Check curve invariants. Curves received from clients must be checked before use
+    pub fn check(&self) -> Result<()> {
+        if self.points.is_empty() {
+            bail!("Fan curve must contain at least one point")
+        }
+        if self.points.iter().any(|point| point.speed > 100) {
+            bail!("Fan speed of curve point must be from 0 to 100 (in percent)")
+        }
+        if self
+            .points
+            .windows(2)
+            .any(|pair| pair[0].temp >= pair[1].temp)
+        {
+            bail!("Fan curve points must be sorted by temperature without duplicates")
+        }
+        Ok(())
+    }
+
+    pub fn points(&self) -> &[CurvePoint] {
+        &self.points
+    }
+
+    /// Fan speed (in percent) for the given temperature
+    pub fn speed_for(&self, temp: u8) -> u8 {
+        let first = self.points[0];
+        if temp <= first.temp {
+            return first.speed;
+        }
+        for pair in self.points.windows(2) {
+            let (low, high) = (pair[0], pair[1]);
+            if temp <= high.temp {
+                let temp_range = (high.temp - low.temp) as i32;
+                let speed_range = high.speed as i32 - low.speed as i32;
+                let offset = (temp - low.temp) as i32;
+                return (low.speed as i32 + speed_range * offset / temp_range) as u8;
+            }
+        }
+        self.points[self.points.len() - 1].speed
+    }
+}
+
+impl Display for FanCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let points = self
+            .points
+            .iter()
+            .map(|point| format!("{}:{}", point.temp, point.speed))
+            .collect::<Vec<_>>();
+        write!(f, "{}", points.join(","))
+    }
+}
+
+impl FromStr for FanCurve {
+    type Err = anyhow::Error;
+
+    /// Parse curve written as `TEMP:SPEED,TEMP:SPEED,...`
+    fn from_str(s: &str) -> Result<Self> {
+        let points = s
+            .split(',')
+            .map(|point| {
+                let (temp, speed) = point
+                    .split_once(':')
+                    .with_context(|| format!("Curve point `{point}` must be TEMP:SPEED"))?;
+                Ok(CurvePoint {
+                    temp: temp
+                        .trim()
+                        .parse()
+                        .context("Parse curve point temperature")?,
+                    speed: speed.trim().parse().context("Parse curve point speed")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::try_new(points)
+    }
+}
+
+/// Smoothing applied to curves to avoid fans oscillation when temperature hovers around a curve point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct CurveTuning {
+    /// How many degrees (°C) temperature must drop below a curve point before speed goes down
+    pub hysteresis: u8,
+    /// Minimum time (in seconds) fan stays at the same speed. Never less than [`WRITE_TIMEOUT_MS`]
+    pub min_dwell_secs: u16,
+    /// Maximum speed change (in percent) per second. 0 means no limit
+    pub max_ramp_per_sec: u8,
+}
+
+impl Default for CurveTuning {
+    fn default() -> Self {
+        Self {
+            hysteresis: 3,
+            min_dwell_secs: 5,
+            max_ramp_per_sec: 10,
+        }
+    }
+}
+
+impl Display for CurveTuning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hysteresis={},dwell={},ramp={}",
+            self.hysteresis, self.min_dwell_secs, self.max_ramp_per_sec
+        )
+    }
+}
+
+impl FromStr for CurveTuning {
+    type Err = anyhow::Error;
+
+    /// Parse tuning written as `KEY=VALUE,...`. Omitted keys take default values
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tuning = Self::default();
+        for option in s.split(',') {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("Tuning option `{option}` must be KEY=VALUE"))?;
+            let value = value.trim();
+            match key.trim() {
+                "hysteresis" => tuning.hysteresis = value.parse().context("Parse hysteresis")?,
+                "dwell" => tuning.min_dwell_secs = value.parse().context("Parse dwell")?,
+                "ramp" => tuning.max_ramp_per_sec = value.parse().context("Parse ramp")?,
+                key => bail!("Unknown tuning option `{key}`. Expected hysteresis, dwell or ramp"),
+            }
+        }
+        Ok(tuning)
+    }
+}
+
+impl CurveTuning {
+    fn min_dwell(&self) -> Duration {
+        Duration::from_secs(self.min_dwell_secs as u64)
+            .max(Duration::from_millis(WRITE_TIMEOUT_MS as u64))
+    }
+}
+
+/// Separate curves for CPU and GPU fans
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct FanCurves {
+    pub cpu: FanCurve,
+    pub gpu: FanCurve,
+    pub tuning: CurveTuning,
+}
+
+impl FanCurves {
+    pub fn check(&self) -> Result<()> {
+        self.cpu.check().context("CPU fan curve")?;
+        self.gpu.check().context("GPU fan curve")
+    }
+
+    /// Load curves saved by the daemon. `None` if no curves were set
+    pub fn load(state_dir: &Path) -> Result<Option<Self>> {
+        let content = match std::fs::read(state_dir.join(FAN_CURVE_FILE)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Read fan curve file"),
+        };
+        let curves = rkyv::from_bytes::<Option<Self>, RkyvError>(&content)
+            .context("Parse fan curve file")?;
+        Ok(curves)
+    }
+
+    /// Save curves to be restored after the daemon restart. `None` disables curves
+    pub fn save(state_dir: &Path, curves: Option<&Self>) -> Result<()> {
+        std::fs::create_dir_all(state_dir).context("Create state directory")?;
+        let bytes = rkyv::to_bytes::<RkyvError>(&curves.cloned())?;
+        std::fs::write(state_dir.join(FAN_CURVE_FILE), bytes).context("Write fan curve file")?;
+        Ok(())
+    }
+}
+
+impl Display for FanCurves {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{};{}", self.cpu, self.gpu, self.tuning)
+    }
+}
+
+impl FromStr for FanCurves {
+    type Err = anyhow::Error;
+
+    /// Parse curves written as `CPU_CURVE[/GPU_CURVE][;TUNING]`. If GPU curve is omitted, CPU one is used for both fans
+    fn from_str(s: &str) -> Result<Self> {
+        let (curves, tuning) = match s.split_once(';') {
+            Some((curves, tuning)) => (curves, tuning.parse().context("Parse curve tuning")?),
+            None => (s, CurveTuning::default()),
+        };
+        let (cpu, gpu) = match curves.split_once('/') {
+            Some((cpu, gpu)) => (
+                cpu.parse().context("Parse CPU fan curve")?,
+                gpu.parse().context("Parse GPU fan curve")?,
+            ),
+            None => {
+                let both = curves.parse::<FanCurve>()?;
+                (both.clone(), both)
+            }
+        };
+        Ok(Self { cpu, gpu, tuning })
+    }
+}
+
+/// Speed of a single fan last written by [`CurveController`]
+#[derive(Debug, Clone, Copy)]
+struct FanStep {
+    speed: u8,
+    /// Dwell time is counted from here
+    changed_at: Instant,
+    /// Last evaluation, ramp is counted from here, so a held speed doesn't build up a big step
+    ticked_at: Instant,
+}
+
+/// Turns curves and temperatures into fan speeds applying [`CurveTuning`]
+#[derive(Debug, Default)]
+pub struct CurveController {
+    cpu: Option<FanStep>,
+    gpu: Option<FanStep>,
+}
+
+impl CurveController {
+    /// Forget previous speeds, e.g. when fan mode was changed by someone else
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Fan mode to be written to EC. Speeds of mode are remembered as written
+    pub fn next_mode(&mut self, curves: &FanCurves, cpu_temp: u8, gpu_temp: u8) -> FanMode {
+        let now = Instant::now();
+        let cpu = Self::next_step(self.cpu, &curves.cpu, &curves.tuning, cpu_temp, now);
+        let gpu = Self::next_step(self.gpu, &curves.gpu, &curves.tuning, gpu_temp, now);
+        self.cpu = Some(cpu);
+        self.gpu = Some(gpu);
+        match (cpu.speed, gpu.speed) {
+            // The same registers state as turbo, so it's read back as turbo
+            (100, 100) => FanMode::Turbo,
+            (cpu, gpu) => FanMode::Fixed { cpu, gpu },
+        }
+    }
+
+    fn next_step(
+        prev: Option<FanStep>,
+        curve: &FanCurve,
+        tuning: &CurveTuning,
+        temp: u8,
+        now: Instant,
+    ) -> FanStep {
+        let target = curve.speed_for(temp);
+        let Some(prev) = prev else {
+            return FanStep {
+                speed: target,
+                changed_at: now,
+                ticked_at: now,
+            };
+        };
+        // Going down, curve is shifted by hysteresis, so the speed drops only when
+        // temperature is noticeably lower than the point where it was raised
+        let target = if target < prev.speed {
+            curve
+                .speed_for(temp.saturating_add(tuning.hysteresis))
+                .min(prev.speed)
+        } else {
+            target
+        };
+        if target == prev.speed || now.duration_since(prev.changed_at) < tuning.min_dwell() {
+            return FanStep {
+                ticked_at: now,
+                ..prev
+            };
+        }
+        let elapsed = now.duration_since(prev.ticked_at);
+        let speed = if tuning.max_ramp_per_sec == 0 {
+            target
+        } else {
+            let max_step = (tuning.max_ramp_per_sec as u64 * elapsed.as_millis() as u64 / 1000)
+                .clamp(1, 100) as u8;
+            if target > prev.speed {
+                target.min(prev.speed.saturating_add(max_step))
+            } else {
+                target.max(prev.speed.saturating_sub(max_step))
+            }
+        };
+        FanStep {
+            speed,
+            changed_at: now,
+            ticked_at: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tuning(s: &str) -> CurveTuning {
+        s.parse().unwrap()
+    }
+
+    fn step(speed: u8, changed_at: Instant) -> Option<FanStep> {
+        Some(FanStep {
+            speed,
+            changed_at,
+            ticked_at: changed_at,
+        })
+    }
+
+    #[test]
+    fn speed_is_interpolated_between_points() {
+        let curve = "50:0,70:50,90:100".parse::<FanCurve>().unwrap();
+        assert_eq!(curve.speed_for(20), 0);
+        assert_eq!(curve.speed_for(60), 25);
+        assert_eq!(curve.speed_for(70), 50);
+        assert_eq!(curve.speed_for(85), 87);
+        assert_eq!(curve.speed_for(100), 100);
+    }
+
+    #[test]
+    fn curves_round_trip() {
+        let s = "50:0,70:50/40:10,80:100;hysteresis=2,dwell=7,ramp=4";
+        assert_eq!(s.parse::<FanCurves>().unwrap().to_string(), s);
+        // Omitted GPU curve and tuning
+        assert_eq!(
+            "50:0,90:100".parse::<FanCurves>().unwrap().to_string(),
+            "50:0,90:100/50:0,90:100;hysteresis=3,dwell=5,ramp=10"
+        );
+        assert!("70:0,50:100".parse::<FanCurves>().is_err());
+        assert!("50:101".parse::<FanCurves>().is_err());
+    }
+
+    #[test]
+    fn speed_is_kept_for_dwell_time() {
+        let curve = "50:0,70:100".parse::<FanCurve>().unwrap();
+        let tuning = tuning("hysteresis=0,dwell=5,ramp=0");
+        let start = Instant::now();
+        let prev = step(0, start);
+
+        let held =
+            CurveController::next_step(prev, &curve, &tuning, 70, start + Duration::from_secs(4));
+        assert_eq!(held.speed, 0);
+        assert_eq!(held.changed_at, start);
+        let raised =
+            CurveController::next_step(prev, &curve, &tuning, 70, start + Duration::from_secs(5));
+        assert_eq!(raised.speed, 100);
+    }
+
+    #[test]
+    fn speed_change_is_limited_by_ramp() {
+        let curve = "50:0,70:100".parse::<FanCurve>().unwrap();
+        let tuning = tuning("hysteresis=0,dwell=0,ramp=10");
+        let start = Instant::now();
+
+        let up = CurveController::next_step(
+            step(0, start),
+            &curve,
+            &tuning,
+            70,
+            start + Duration::from_secs(3),
+        );
+        assert_eq!(up.speed, 30);
+        let down = CurveController::next_step(
+            step(100, start),
+            &curve,
+            &tuning,
+            50,
+            start + Duration::from_secs(4),
+        );
+        assert_eq!(down.speed, 60);
+    }
+
+    #[test]
+    fn held_speed_ramps_from_the_last_tick() {
+        let curve = "50:0,70:100".parse::<FanCurve>().unwrap();
+        let tuning = tuning("hysteresis=0,dwell=0,ramp=10");
+        let start = Instant::now();
+
+        // 56°C keeps 30% for a minute
+        let held = CurveController::next_step(
+            step(30, start),
+            &curve,
+            &tuning,
+            56,
+            start + Duration::from_secs(58),
+        );
+        assert_eq!(held.speed, 30);
+        // Spike gets a single ramp step of 2 s since the last tick, not of the whole minute
+        let raised = CurveController::next_step(
+            Some(held),
+            &curve,
+            &tuning,
+            70,
+            start + Duration::from_secs(60),
+        );
+        assert_eq!(raised.speed, 50);
+    }
+
+    #[test]
+    fn falling_temperature_is_held_by_hysteresis() {
+        // 5% per °C
+        let curve = "50:0,70:100".parse::<FanCurve>().unwrap();
+        let tuning = tuning("hysteresis=3,dwell=0,ramp=0");
+        let start = Instant::now();
+        let prev = step(50, start);
+        let later = start + Duration::from_secs(10);
+
+        // 58°C is within 3°C of 60°C where the speed was raised
+        let held = CurveController::next_step(prev, &curve, &tuning, 58, later);
+        assert_eq!(held.speed, 50);
+        // 56°C drops to the speed of 59°C
+        let lowered = CurveController::next_step(prev, &curve, &tuning, 56, later);
+        assert_eq!(lowered.speed, 45);
+        // Going up isn't affected
+        let raised = CurveController::next_step(prev, &curve, &tuning, 61, later);
+        assert_eq!(raised.speed, 55);
+    }
+
+    #[test]
+    fn full_speed_is_turbo() {
+        let curves = "50:0,70:100".parse::<FanCurves>().unwrap();
+        let mut controller = CurveController::default();
+        assert_eq!(controller.next_mode(&curves, 80, 75), FanMode::Turbo);
+        controller.reset();
+        assert_eq!(
+            controller.next_mode(&curves, 80, 60),
+            FanMode::Fixed { cpu: 100, gpu: 50 }
+        );
+    }
+}
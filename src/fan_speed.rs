@@ -3,7 +3,7 @@ use std::{fmt::Display, str::FromStr};
 use crate::{
     daemon::codec::DaemonReq,
     impl_read,
-    registers::*,
+    profile::{profile, BitReg},
     traits::{ECHandler, InvokeDaemon, ReadEC, WriteEC},
     BitState, RWData, Reg,
 };
@@ -12,12 +12,17 @@ use rkyv::{Archive, Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Archive, Serialize, Deserialize)]
 /// Different fan speeds. Names as they are presented in official Gigabyte Control Center app
-/// TODO: custom fan speed (fixed and curved)
+/// TODO: custom curved fan speed
 pub enum FanMode {
     Normal,
     Eco,
     Power,
     Turbo,
+    /// Fixed fan speeds in percent (0 - min value, 100 - max value of model fixed speed registers)
+    Fixed {
+        cpu: u8,
+        gpu: u8,
+    },
     Unsupported,
 }
 
@@ -28,6 +33,7 @@ impl Display for FanMode {
             FanMode::Eco => "eco",
             FanMode::Power => "power",
             FanMode::Turbo => "turbo",
+            FanMode::Fixed { cpu, gpu } => return write!(f, "fixed:{cpu},{gpu}"),
             FanMode::Unsupported => "unsupported",
         };
         write!(f, "{to_write}")
@@ -43,111 +49,163 @@ impl FromStr for FanMode {
             "eco" => Ok(FanMode::Eco),
             "power" => Ok(FanMode::Power),
             "turbo" => Ok(FanMode::Turbo),
-            _ => Err("Failed to convert string to FanMode"),
+            _ => {
+                // Fixed mode is written as `fixed:CPU,GPU` or `fixed:BOTH` (in percent)
+                let Some(speeds) = s.strip_prefix("fixed:") else {
+                    return Err("Failed to convert string to FanMode");
+                };
+                let parse_percent = |val: &str| {
+                    val.trim()
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|val| *val <= 100)
+                        .ok_or("Fixed fan speed must be from 0 to 100 (in percent)")
+                };
+                let (cpu, gpu) = match speeds.split_once(',') {
+                    Some((cpu, gpu)) => (parse_percent(cpu)?, parse_percent(gpu)?),
+                    None => {
+                        let both = parse_percent(speeds)?;
+                        (both, both)
+                    }
+                };
+                Ok(FanMode::Fixed { cpu, gpu })
+            }
         }
     }
 }
 
 impl WriteEC for FanMode {
     fn data_to_write(&self) -> Vec<RWData> {
+        let regs = &profile().fan_mode;
+        let fixed_regs = &profile().fixed_speed;
         match self {
             FanMode::Normal => vec![
                 RWData::U1 {
-                    reg: ECO_MODE.0,
-                    pos: ECO_MODE.1,
+                    reg: regs.eco.reg,
+                    pos: regs.eco.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: POWER_MODE.0,
-                    pos: POWER_MODE.1,
+                    reg: regs.power.reg,
+                    pos: regs.power.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: CUSTOM_MODE.0,
-                    pos: CUSTOM_MODE.1,
+                    reg: regs.custom.reg,
+                    pos: regs.custom.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: FIXED_MODE.0,
-                    pos: FIXED_MODE.1,
+                    reg: regs.fixed.reg,
+                    pos: regs.fixed.bit,
                     state: BitState::Disabled,
                 },
             ],
 
             FanMode::Eco => vec![
                 RWData::U1 {
-                    reg: ECO_MODE.0,
-                    pos: ECO_MODE.1,
+                    reg: regs.eco.reg,
+                    pos: regs.eco.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U1 {
-                    reg: POWER_MODE.0,
-                    pos: POWER_MODE.1,
+                    reg: regs.power.reg,
+                    pos: regs.power.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: CUSTOM_MODE.0,
-                    pos: CUSTOM_MODE.1,
+                    reg: regs.custom.reg,
+                    pos: regs.custom.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: FIXED_MODE.0,
-                    pos: FIXED_MODE.1,
+                    reg: regs.fixed.reg,
+                    pos: regs.fixed.bit,
                     state: BitState::Disabled,
                 },
             ],
 
             FanMode::Power => vec![
                 RWData::U1 {
-                    reg: ECO_MODE.0,
-                    pos: ECO_MODE.1,
+                    reg: regs.eco.reg,
+                    pos: regs.eco.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: POWER_MODE.0,
-                    pos: POWER_MODE.1,
+                    reg: regs.power.reg,
+                    pos: regs.power.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U1 {
-                    reg: CUSTOM_MODE.0,
-                    pos: CUSTOM_MODE.1,
+                    reg: regs.custom.reg,
+                    pos: regs.custom.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: FIXED_MODE.0,
-                    pos: FIXED_MODE.1,
+                    reg: regs.fixed.reg,
+                    pos: regs.fixed.bit,
                     state: BitState::Disabled,
                 },
             ],
 
             FanMode::Turbo => vec![
                 RWData::U1 {
-                    reg: ECO_MODE.0,
-                    pos: ECO_MODE.1,
+                    reg: regs.eco.reg,
+                    pos: regs.eco.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: POWER_MODE.0,
-                    pos: POWER_MODE.1,
+                    reg: regs.power.reg,
+                    pos: regs.power.bit,
                     state: BitState::Disabled,
                 },
                 RWData::U1 {
-                    reg: CUSTOM_MODE.0,
-                    pos: CUSTOM_MODE.1,
+                    reg: regs.custom.reg,
+                    pos: regs.custom.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U1 {
-                    reg: FIXED_MODE.0,
-                    pos: FIXED_MODE.1,
+                    reg: regs.fixed.reg,
+                    pos: regs.fixed.bit,
                     state: BitState::Enabled,
                 },
                 RWData::U8 {
-                    reg: FIXED_SPEED_FAN1,
-                    value: FIXED_SPEED_MAX_VALUE,
+                    reg: fixed_regs.cpu_fan,
+                    value: fixed_regs.max_value,
                 },
                 RWData::U8 {
-                    reg: FIXED_SPEED_FAN2,
-                    value: FIXED_SPEED_MAX_VALUE,
+                    reg: fixed_regs.gpu_fan,
+                    value: fixed_regs.max_value,
+                },
+            ],
+            FanMode::Fixed { cpu, gpu } => vec![
+                RWData::U1 {
+                    reg: regs.eco.reg,
+                    pos: regs.eco.bit,
+                    state: BitState::Disabled,
+                },
+                RWData::U1 {
+                    reg: regs.power.reg,
+                    pos: regs.power.bit,
+                    state: BitState::Disabled,
+                },
+                RWData::U1 {
+                    reg: regs.custom.reg,
+                    pos: regs.custom.bit,
+                    state: BitState::Enabled,
+                },
+                RWData::U1 {
+                    reg: regs.fixed.reg,
+                    pos: regs.fixed.bit,
+                    state: BitState::Enabled,
+                },
+                RWData::U8 {
+                    reg: fixed_regs.cpu_fan,
+                    value: percent_to_fixed_speed(*cpu),
+                },
+                RWData::U8 {
+                    reg: fixed_regs.gpu_fan,
+                    value: percent_to_fixed_speed(*gpu),
                 },
             ],
             FanMode::Unsupported => vec![],
@@ -157,27 +215,37 @@ impl WriteEC for FanMode {
 
 impl ReadEC for FanMode {
     fn data_to_read() -> Vec<RWData> {
+        let regs = &profile().fan_mode;
+        let fixed_regs = &profile().fixed_speed;
         vec![
             RWData::U1 {
-                reg: ECO_MODE.0,
-                pos: ECO_MODE.1,
+                reg: regs.eco.reg,
+                pos: regs.eco.bit,
                 state: BitState::Disabled,
             },
             RWData::U1 {
-                reg: POWER_MODE.0,
-                pos: POWER_MODE.1,
+                reg: regs.power.reg,
+                pos: regs.power.bit,
                 state: BitState::Disabled,
             },
             RWData::U1 {
-                reg: CUSTOM_MODE.0,
-                pos: CUSTOM_MODE.1,
+                reg: regs.custom.reg,
+                pos: regs.custom.bit,
                 state: BitState::Disabled,
             },
             RWData::U1 {
-                reg: FIXED_MODE.0,
-                pos: FIXED_MODE.1,
+                reg: regs.fixed.reg,
+                pos: regs.fixed.bit,
                 state: BitState::Disabled,
             },
+            RWData::U8 {
+                reg: fixed_regs.cpu_fan,
+                value: 0,
+            },
+            RWData::U8 {
+                reg: fixed_regs.gpu_fan,
+                value: 0,
+            },
         ]
     }
 }
@@ -191,13 +259,18 @@ impl InvokeDaemon for FanMode {
 impl FanMode {
     pub async fn current_mode(ec: &mut impl ECHandler) -> Result<Self> {
         let read_data = ec.read_data::<Self>().await?;
+        let regs = &profile().fan_mode;
+        let fixed_regs = &profile().fixed_speed;
 
-        let is_mode = |reg_to_check: Reg| {
+        let is_mode = |bit_to_check: &BitReg| {
             read_data
                 .iter()
                 .find_map(|d| {
-                    if let RWData::U1 { reg, pos: _, state } = d {
-                        if *reg == reg_to_check && state == &BitState::Enabled {
+                    if let RWData::U1 { reg, pos, state } = d {
+                        if *reg == bit_to_check.reg
+                            && *pos == bit_to_check.bit
+                            && state == &BitState::Enabled
+                        {
                             return Some(());
                         }
                     }
@@ -206,23 +279,82 @@ impl FanMode {
                 .is_some()
         };
 
-        if is_mode(ECO_MODE.0) {
+        if is_mode(&regs.eco) {
             Ok(Self::Eco)
-        } else if is_mode(POWER_MODE.0) {
+        } else if is_mode(&regs.power) {
             Ok(Self::Power)
-        } else if is_mode(CUSTOM_MODE.0) && is_mode(FIXED_MODE.0) {
-            Ok(Self::Turbo)
-        } else if !is_mode(ECO_MODE.0)
-            && !is_mode(POWER_MODE.0)
-            && !is_mode(CUSTOM_MODE.0)
-            && !is_mode(FIXED_MODE.0)
+        } else if is_mode(&regs.custom) && is_mode(&regs.fixed) {
+            let fixed_speed = |reg_to_check: Reg| {
+                read_data
+                    .iter()
+                    .find_map(|d| match d {
+                        RWData::U8 { reg, value } if *reg == reg_to_check => Some(*value),
+                        _ => None,
+                    })
+                    .unwrap_or_default()
+            };
+            let fan1 = fixed_speed(fixed_regs.cpu_fan);
+            let fan2 = fixed_speed(fixed_regs.gpu_fan);
+            // Turbo is just fixed mode with max speed for both fans
+            if fan1 == fixed_regs.max_value && fan2 == fixed_regs.max_value {
+                Ok(Self::Turbo)
+            } else {
+                Ok(Self::Fixed {
+                    cpu: fixed_speed_to_percent(fan1),
+                    gpu: fixed_speed_to_percent(fan2),
+                })
+            }
+        } else if !is_mode(&regs.eco)
+            && !is_mode(&regs.power)
+            && !is_mode(&regs.custom)
+            && !is_mode(&regs.fixed)
         {
             Ok(Self::Normal)
         } else {
             Ok(Self::Unsupported)
         }
     }
+
+    /// Mode following this one in `modes`. The first one if this isn't there, e.g. fixed speeds set by fan curves
+    pub fn cycle(self, modes: &[FanMode]) -> Option<FanMode> {
+        let next = modes
+            .iter()
+            .position(|mode| *mode == self)
+            .map_or(0, |pos| (pos + 1) % modes.len());
+        modes.get(next).copied()
+    }
+
+    /// Whether the mode can be written to EC as is
+    pub fn is_valid(&self) -> bool {
+        match self {
+            FanMode::Fixed { cpu, gpu } => *cpu <= 100 && *gpu <= 100,
+            FanMode::Unsupported => false,
+            _ => true,
+        }
+    }
+}
+
+/// Convert fixed speed in percent to the raw EC value
+fn percent_to_fixed_speed(percent: u8) -> u8 {
+    let (min, max) = (
+        profile().fixed_speed.min_value,
+        profile().fixed_speed.max_value,
+    );
+    let range = (max - min) as u16;
+    let percent = percent.min(100) as u16;
+    min + ((percent * range + 50) / 100) as u8
+}
+
+/// Convert raw EC fixed speed value to percent
+fn fixed_speed_to_percent(value: u8) -> u8 {
+    let (min, max) = (
+        profile().fixed_speed.min_value,
+        profile().fixed_speed.max_value,
+    );
+    let range = (max - min) as u16;
+    let value = value.clamp(min, max) - min;
+    ((value as u16 * 100 + range / 2) / range) as u8
 }
 
-impl_read! {U16, CpuFanSpeed, RWData::U16 { reg: CPU_FAN_SPEED, value: 0 }}
-impl_read! {U16, GpuFanSpeed, RWData::U16 { reg: GPU_FAN_SPEED, value: 0 }}
+impl_read! {U16, CpuFanSpeed, RWData::U16 { reg: profile().fan_speed.cpu, value: 0, byte_order: profile().fan_speed.byte_order }}
+impl_read! {U16, GpuFanSpeed, RWData::U16 { reg: profile().fan_speed.gpu, value: 0, byte_order: profile().fan_speed.byte_order }}
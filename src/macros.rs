@@ -46,7 +46,7 @@ macro_rules! impl_read {
                 ec: &mut impl $crate::traits::ECHandler,
             ) -> ::anyhow::Result<$name> {
                 let read_data = ec.read_data::<Self>().await?;
-                let RWData::U16 { reg: _, value } = read_data[0] else {
+                let RWData::U16 { value, .. } = read_data[0] else {
                     unreachable!()
                 };
                 Ok(Self(value))
@@ -6,48 +6,165 @@
         reason = "Slint generated code contains warnings, so mute it until it fixed"
     )
 )]
+mod backend;
+mod bar;
 mod bat;
 mod cli;
 mod common;
+mod config;
 mod daemon;
+mod dmi;
 mod ec;
+mod ec_dump;
+mod fan_curve;
 mod fan_speed;
 mod macros;
 mod monitor;
-mod registers;
+mod output;
+mod power;
+mod profile;
+mod raw;
 mod temp;
 mod traits;
 #[cfg(feature = "gui")]
 mod ui;
 
 use std::{
-    process::{Command, Stdio},
-    str::FromStr,
+    path::PathBuf,
+    process::{Command, ExitCode, Stdio},
+    time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
+use backend::{BackendKind, EcAccess};
+use bar::{BarStyle, Thresholds};
 use bat::BatThreshold;
+use clap::{ArgMatches, ValueEnum as _};
 use cli::{cli, DaemonCommands};
 use common::{Handler, EXIT_MSG};
-use daemon::server::start_daemon;
+use config::Config;
+use daemon::{client::DaemonClient, error::DaemonError, server::start_daemon};
+use dmi::Dmi;
 use ec::*;
 use env_logger::{init_from_env, Env};
+use fan_curve::FanCurves;
 use fan_speed::FanMode;
 use libc::geteuid;
 use log::{debug, info, warn};
 use monitor::Monitor;
+use output::Format;
+use power::{PowerRule, PowerSource};
+use profile::{set_profile, Profile, DEFAULT_PROFILE};
+use raw::{RawWidth, RawWrite};
 use traits::ECHandler;
 
-fn main() -> Result<()> {
-    let cli = cli();
-    let args = std::env::args().collect::<Vec<_>>();
+fn main() -> ExitCode {
+    let matches = match cli().try_get_matches() {
+        Ok(matches) => matches,
+        Err(err) => match raw_format() {
+            // Help, version and usage errors in text are printed by clap
+            Format::Text => err.exit(),
+            _ if !err.use_stderr() => err.exit(),
+            format => {
+                // The first line without usage hints, e.g. "invalid value '200' for ..."
+                let message = err.to_string();
+                let message = message.lines().next().unwrap_or_default();
+                let message = message.strip_prefix("error: ").unwrap_or(message);
+                let err = DaemonError::InvalidArgument(message.to_owned());
+                return output::report_error(format, &err.into());
+            }
+        },
+    };
+    // SAFETY: the argument has default value
+    let format = *matches.get_one::<Format>("format").unwrap();
+    match run(&matches, format) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => output::report_error(format, &err),
+    }
+}
+
+/// `--format` taken from raw arguments, as they failed to parse. Text if it's missing or invalid
+fn raw_format() -> Format {
+    let mut args = std::env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned());
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--format") {
+            Some("") => args.next(),
+            Some(value) => value.strip_prefix('=').map(str::to_owned),
+            None => continue,
+        };
+        if let Some(format) = value.and_then(|value| Format::from_str(&value, false).ok()) {
+            return format;
+        }
+    }
+    Format::default()
+}
+
+fn run(matches: &ArgMatches, format: Format) -> Result<()> {
     let euid = unsafe { geteuid() };
-    let matches = cli.get_matches_from(args);
     if matches.get_flag("logs") {
         init_from_env(Env::default().filter_or("RUST_LOG", "info"));
     }
     debug!("Matches ready");
 
+    if let Some(("config", matches)) = matches.subcommand() {
+        return config_command(matches);
+    }
+    if let Some(("power", matches)) = matches.subcommand() {
+        return power_command(matches, format);
+    }
+    // Only daemon is configured by config file, clients take just socket path from it
+    let daemon_cmd = matches.get_one::<DaemonCommands>("daemon");
+    let config = match daemon_cmd {
+        Some(DaemonCommands::Run) => Config::load()?,
+        _ => Config::default(),
+    };
+    select_profile(
+        matches
+            .get_one::<String>("force_model")
+            .or(config.model.as_ref()),
+    )?;
+    let access = match matches.get_one::<PathBuf>("ec_image") {
+        Some(image) => EcAccess::Image(image.clone()),
+        // SAFETY: the argument has default value
+        None => EcAccess::Hardware(*matches.get_one::<BackendKind>("ec_backend").unwrap()),
+    };
+
+    if let Some(("ec", ec_matches)) = matches.subcommand() {
+        return ec_command(ec_matches, &access, euid, format);
+    }
+    if let Some(("watch", matches)) = matches.subcommand() {
+        // SAFETY: the argument has default value
+        let interval = *matches.get_one::<u64>("interval").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        return runtime.block_on(async {
+            let mut ec = handler(&access, euid).await?;
+            monitor::watch(&mut ec, Duration::from_millis(interval)).await
+        });
+    }
+    if let Some(("bar", matches)) = matches.subcommand() {
+        // SAFETY: the arguments have default values
+        let style = *matches.get_one::<BarStyle>("style").unwrap();
+        let interval = *matches.get_one::<u64>("interval").unwrap();
+        let thresholds = Thresholds {
+            warning: *matches.get_one::<u8>("warning_temp").unwrap(),
+            critical: *matches.get_one::<u8>("critical_temp").unwrap(),
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        return runtime.block_on(async {
+            let mut ec = handler(&access, euid).await?;
+            bar::run(&mut ec, style, thresholds, Duration::from_millis(interval)).await
+        });
+    }
+    if let Some(("fan", matches)) = matches.subcommand() {
+        return fan_command(matches, &access, euid, format);
+    }
+
     #[cfg(feature = "gui")]
     if std::env::args().len() == 1 {
         let runtime = tokio::runtime::Builder::new_multi_thread()
@@ -58,8 +175,10 @@ fn main() -> Result<()> {
         std::process::exit(0);
     }
 
-    if let Some(daemon_cmd) = matches.get_one::<DaemonCommands>("daemon") {
-        if euid != 0 {
+    if let Some(daemon_cmd) = daemon_cmd {
+        // Simulated daemon doesn't need root
+        let simulated_run = matches!(daemon_cmd, DaemonCommands::Run) && access.is_simulated();
+        if euid != 0 && !simulated_run {
             rerun_as_root()
         }
         match daemon_cmd {
@@ -67,7 +186,9 @@ fn main() -> Result<()> {
                 let runtime = tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()?;
-                runtime.block_on(async { start_daemon().await.context("Start daemon") })?;
+                runtime.block_on(async {
+                    start_daemon(&access, &config).await.context("Start daemon")
+                })?;
             }
             DaemonCommands::Install => {
                 #[cfg(feature = "self-packed")]
@@ -99,39 +220,64 @@ fn main() -> Result<()> {
         .enable_all()
         .build()?;
     runtime.block_on(async {
-        let mut ec = match Handler::new().await {
-            Ok(ec) => ec,
-            Err(err) => {
-                if euid != 0 {
-                    rerun_as_root()
-                } else {
-                    bail!("Failed to run gigacenter: {err}");
-                }
-            }
-        };
+        let mut ec = handler(&access, euid).await?;
 
-        if let Some(fan_mode) = matches.get_one::<String>("fan_mode") {
-            // SAFETY: fan_mode from cli guaranteed to be parsed be FanMode struct
-            let fm = FanMode::from_str(fan_mode).unwrap();
-            _ = ec.write_data(&fm).await?;
+        if let Some(fan_mode) = matches.get_one::<FanMode>("fan_mode") {
+            _ = ec.write_data(fan_mode).await?;
             info!("Fan mode set to {fan_mode}");
         } else if matches.contains_id("fan_mode") {
             let fan_mode = FanMode::current_mode(&mut ec).await?;
-            println!("{fan_mode}");
+            output::print(
+                format,
+                fan_mode,
+                vec![("fan_mode", fan_mode.to_string().as_str().into())],
+            );
+        }
+
+        if let Some(curves) = matches.get_one::<Option<FanCurves>>("fan_curves") {
+            let Handler::DaemonClient(daemon) = &mut ec else {
+                bail!("Fan curves are driven by daemon. Install it with `gigacenter --daemon install`");
+            };
+            _ = daemon.set_fan_curves(curves.clone()).await?;
+            match curves {
+                Some(curves) => info!("Fan curves set to {curves}"),
+                None => info!("Fan curves disabled"),
+            }
+        } else if matches.contains_id("fan_curves") {
+            let Handler::DaemonClient(daemon) = &mut ec else {
+                bail!("Fan curves are driven by daemon. Install it with `gigacenter --daemon install`");
+            };
+            let curves = daemon.fan_curves().await?;
+            let text = curves.as_ref().map_or("off".to_owned(), ToString::to_string);
+            output::print(
+                format,
+                &text,
+                vec![(
+                    "fan_curves",
+                    curves.map(|_| text.as_str()).into(),
+                )],
+            );
         }
 
         if let Some(threshold) = matches.get_one::<u8>("bat_threshold") {
             _ = ec.write_data(&BatThreshold::new(*threshold)).await?;
             info!("Battery threshold set to {}", *threshold);
         } else if matches.contains_id("bat_threshold") {
-            let fan_mode = BatThreshold::current_state(&mut ec).await?;
-            println!("{}", *fan_mode);
+            let threshold = BatThreshold::current_state(&mut ec).await?;
+            output::print(
+                format,
+                *threshold,
+                vec![(
+                    "bat_threshold_percent",
+                    u64::from(threshold.actual_value()).into(),
+                )],
+            );
         }
         if matches.get_flag("show") {
             let monitor = Monitor::try_new(&mut ec)
                 .await
                 .context("Creating monitor")?;
-            println!("{}", monitor);
+            output::print(format, monitor, monitor.fields());
         }
         Ok::<_, anyhow::Error>(())
     })?;
@@ -139,6 +285,225 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// `gigacenter ec ...`. Dumps are taken from EC directly, single registers go through daemon if available
+fn ec_command(matches: &ArgMatches, access: &EcAccess, euid: u32, format: Format) -> Result<()> {
+    match matches.subcommand() {
+        Some(("diff", matches)) => {
+            // SAFETY: arguments are required
+            let old = matches.get_one::<PathBuf>("old").unwrap();
+            let new = matches.get_one::<PathBuf>("new").unwrap();
+            ec_dump::diff(old, new)
+        }
+        Some(("dump", matches)) => {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(async {
+                let mut ec = match EmbeddedController::new(access).await {
+                    Ok(ec) => ec,
+                    Err(err) => {
+                        if euid != 0 && !access.is_simulated() {
+                            rerun_as_root()
+                        } else {
+                            return Err(err).context("Open EC");
+                        }
+                    }
+                };
+                if matches.get_flag("watch") {
+                    // SAFETY: the argument has default value
+                    let interval = *matches.get_one::<u64>("interval").unwrap();
+                    ec_dump::watch(&mut ec, Duration::from_millis(interval)).await
+                } else {
+                    let output = matches.get_one::<PathBuf>("output");
+                    ec_dump::dump(&mut ec, output.map(PathBuf::as_path)).await
+                }
+            })
+        }
+        Some((cmd @ ("read" | "write"), matches)) => {
+            // SAFETY: the argument is required
+            let reg = *matches.get_one::<u8>("reg").unwrap();
+            let bit = matches
+                .get_one::<u8>("bit")
+                .map(|bit| BitPos::try_new(*bit))
+                .transpose()?;
+            if cmd == "write" && !matches.get_flag("confirm") {
+                bail!("Raw EC writes may damage your hardware. Pass `--i-know-what-im-doing` to confirm");
+            }
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(async {
+                let mut ec = handler(access, euid).await?;
+                let byte_order = if matches.get_flag("little_endian") {
+                    ByteOrder::Little
+                } else {
+                    ByteOrder::Big
+                };
+                let u16 = matches.get_flag("u16");
+                if cmd == "read" {
+                    let width = match bit {
+                        Some(pos) => RawWidth::Bit(pos),
+                        None if u16 => RawWidth::U16(byte_order),
+                        None => RawWidth::U8,
+                    };
+                    let value = raw::read(&mut ec, reg, width).await?;
+                    output::print(format, value, value.fields());
+                    return Ok(());
+                }
+                // SAFETY: the argument is required
+                let value = *matches.get_one::<u16>("value").unwrap();
+                let data = match bit {
+                    Some(pos) => RWData::U1 {
+                        reg,
+                        pos,
+                        state: match value {
+                            0 => BitState::Disabled,
+                            1 => BitState::Enabled,
+                            _ => bail!("Bit value must be 0 or 1"),
+                        },
+                    },
+                    None if u16 => RWData::U16 {
+                        reg,
+                        value,
+                        byte_order,
+                    },
+                    None => RWData::U8 {
+                        reg,
+                        value: u8::try_from(value).context(
+                            "Byte value must be from 0 to 255, use `--u16` for 16-bit values",
+                        )?,
+                    },
+                };
+                raw::write(&mut ec, RawWrite(data)).await
+            })
+        }
+        _ => unreachable!("Subcommand is required"),
+    }
+}
+
+/// `gigacenter fan ...`
+fn fan_command(matches: &ArgMatches, access: &EcAccess, euid: u32, format: Format) -> Result<()> {
+    match matches.subcommand() {
+        Some(("cycle", matches)) => {
+            // SAFETY: the argument has default value
+            let modes = matches
+                .get_many::<FanMode>("modes")
+                .unwrap()
+                .copied()
+                .collect::<Vec<_>>();
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(async {
+                let mut ec = handler(access, euid).await?;
+                let current = FanMode::current_mode(&mut ec).await?;
+                let Some(fan_mode) = current.cycle(&modes) else {
+                    bail!("No fan modes to cycle through");
+                };
+                _ = ec.write_data(&fan_mode).await?;
+                info!("Fan mode switched from {current} to {fan_mode}");
+                output::print(
+                    format,
+                    fan_mode,
+                    vec![("fan_mode", fan_mode.to_string().as_str().into())],
+                );
+                Ok(())
+            })
+        }
+        _ => unreachable!("Subcommand is required"),
+    }
+}
+
+/// `gigacenter config ...`
+fn config_command(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("check", matches)) => {
+            let path = matches
+                .get_one::<PathBuf>("file")
+                .cloned()
+                .unwrap_or_else(Config::path);
+            _ = Config::load_from(&path)?;
+            println!("Config {} is valid", path.display());
+            Ok(())
+        }
+        _ => unreachable!("Subcommand is required"),
+    }
+}
+
+/// `gigacenter power ...`. Rules are kept and applied by daemon
+fn power_command(matches: &ArgMatches, format: Format) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut daemon = DaemonClient::connect().await.context(
+            "Power rules are applied by daemon. Install it with `gigacenter --daemon install`",
+        )?;
+        let mut rules = daemon.power_rules().await?;
+        let Some(source) = matches.get_one::<PowerSource>("source") else {
+            output::print(format, rules, rules.fields());
+            return Ok(());
+        };
+        let rule = rules.rule_mut(*source);
+        let fan_mode = matches.get_one::<FanMode>("fan_mode");
+        let bat_threshold = matches.get_one::<u8>("bat_threshold");
+        if matches.get_flag("clear") {
+            *rule = PowerRule::default();
+        } else if fan_mode.is_some() || bat_threshold.is_some() {
+            if let Some(fan_mode) = fan_mode {
+                rule.fan_mode = Some(*fan_mode);
+            }
+            if let Some(threshold) = bat_threshold {
+                rule.bat_threshold = Some(*threshold);
+            }
+        } else {
+            output::print(format, *rule, rule.fields());
+            return Ok(());
+        }
+        let rule = *rule;
+        _ = daemon.set_power_rules(rules).await?;
+        info!("Power rule for {source} set to {rule}");
+        Ok(())
+    })
+}
+
+/// Use forced profile or detect it by DMI. Unknown models get read-only default profile
+fn select_profile(force_model: Option<&String>) -> Result<()> {
+    if let Some(model) = force_model {
+        let profile = Profile::load(model)?;
+        warn!(
+            "Model profile {} is forced. Use it at your own risk",
+            profile.name
+        );
+        return set_profile(profile, true);
+    }
+    let dmi = Dmi::read();
+    match Profile::detect(&dmi) {
+        Some(profile) => {
+            info!("Detected {} model", profile.name);
+            set_profile(profile, true)
+        }
+        None => {
+            warn!("Unknown laptop model {dmi}. Only reading is allowed, use `--force-model` to enable writes");
+            set_profile(Profile::load(DEFAULT_PROFILE)?, false)
+        }
+    }
+}
+
+/// Daemon client or EC opened directly. Reruns gigacenter as root if neither is available
+async fn handler(access: &EcAccess, euid: u32) -> Result<Handler> {
+    match Handler::new(access).await {
+        Ok(ec) => Ok(ec),
+        Err(err) => {
+            if euid != 0 && !access.is_simulated() {
+                rerun_as_root()
+            } else {
+                bail!("Failed to run gigacenter: {err}");
+            }
+        }
+    }
+}
+
 fn rerun_as_root() -> ! {
     warn!("Command need to be run as root. Try rerun via `pkexec`");
     let args = std::env::args();
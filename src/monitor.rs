@@ -1,15 +1,23 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    io::{IsTerminal as _, Write as _},
+    time::Duration,
+};
 
 use crate::{
     bat::BatThreshold,
+    common::Handler,
+    daemon::subscribe::DaemonEvent,
     fan_speed::{CpuFanSpeed, FanMode, GpuFanSpeed},
+    output::Field,
     temp::{CpuTemp, GpuTemp},
     traits::ECHandler,
 };
 use anyhow::Result;
+use rkyv::{Archive, Deserialize, Serialize};
 
 /// Type describing current machine state
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Archive, Serialize, Deserialize)]
 pub struct Monitor {
     pub fan_mode: FanMode,
     pub cpu_fan_speed: CpuFanSpeed,
@@ -32,6 +40,23 @@ impl Monitor {
     }
 }
 
+impl Monitor {
+    /// Fields of machine readable output, see [`crate::output::Format`]
+    pub fn fields(&self) -> Vec<(&'static str, Field)> {
+        vec![
+            ("fan_mode", self.fan_mode.to_string().as_str().into()),
+            (
+                "bat_threshold_percent",
+                u64::from(self.bat_threshold.actual_value()).into(),
+            ),
+            ("cpu_temp_c", u64::from(*self.cpu_temp).into()),
+            ("gpu_temp_c", u64::from(*self.gpu_temp).into()),
+            ("cpu_fan_speed_rpm", u64::from(*self.cpu_fan_speed).into()),
+            ("gpu_fan_speed_rpm", u64::from(*self.gpu_fan_speed).into()),
+        ]
+    }
+}
+
 impl Display for Monitor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -53,3 +78,158 @@ impl Display for Monitor {
         )
     }
 }
+
+/// Min, max and average of a value since watch start
+#[derive(Debug, Default, Clone, Copy)]
+struct Stat {
+    min: u16,
+    max: u16,
+    sum: u64,
+    count: u64,
+}
+
+impl Stat {
+    fn add(&mut self, value: u16) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += u64::from(value);
+        self.count += 1;
+    }
+
+    /// Rounded to the nearest integer
+    fn avg(&self) -> u64 {
+        (self.sum + self.count / 2) / self.count.max(1)
+    }
+}
+
+/// Machine state with statistics of every snapshot since start, drawn by `gigacenter watch`
+#[derive(Debug, Default)]
+struct WatchScreen {
+    monitor: Option<Monitor>,
+    cpu_temp: Stat,
+    gpu_temp: Stat,
+    cpu_fan_speed: Stat,
+    gpu_fan_speed: Stat,
+}
+
+impl WatchScreen {
+    fn add(&mut self, monitor: Monitor) {
+        self.cpu_temp.add((*monitor.cpu_temp).into());
+        self.gpu_temp.add((*monitor.gpu_temp).into());
+        self.cpu_fan_speed.add(*monitor.cpu_fan_speed);
+        self.gpu_fan_speed.add(*monitor.gpu_fan_speed);
+        self.monitor = Some(monitor);
+    }
+
+    /// Redraw in place on terminal, otherwise append, so piped output has no escape sequences
+    fn draw(&self, terminal: bool) {
+        if terminal {
+            // Clear screen and move cursor home
+            print!("\x1b[2J\x1b[H{self}");
+        } else {
+            println!("{self}");
+        }
+        _ = std::io::stdout().flush();
+    }
+}
+
+impl Display for WatchScreen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(monitor) = &self.monitor else {
+            return Ok(());
+        };
+        writeln!(f, "{monitor}")?;
+        writeln!(
+            f,
+            "{:<24}{:>6}{:>6}{:>6}",
+            format!("Since start ({}):", self.cpu_temp.count),
+            "min",
+            "max",
+            "avg"
+        )?;
+        for (name, stat) in [
+            ("CPU Temp", self.cpu_temp),
+            ("GPU Temp", self.gpu_temp),
+            ("CPU fan speed", self.cpu_fan_speed),
+            ("GPU fan speed", self.gpu_fan_speed),
+        ] {
+            writeln!(
+                f,
+                "{name:<24}{:>6}{:>6}{:>6}",
+                stat.min,
+                stat.max,
+                stat.avg()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Pass machine state to `update` until Ctrl-C. Daemon pushes snapshots and fan mode changes made by
+/// other clients right away, EC used directly is polled. `sampled` is false if only fan mode changed
+/// since the last snapshot
+pub async fn follow(
+    ec: &mut Handler,
+    interval: Duration,
+    mut update: impl FnMut(&Monitor, bool) -> Result<()>,
+) -> Result<()> {
+    tokio::select! {
+        res = follow_ec(ec, interval, &mut update) => res,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+async fn follow_ec(
+    ec: &mut Handler,
+    interval: Duration,
+    update: &mut impl FnMut(&Monitor, bool) -> Result<()>,
+) -> Result<()> {
+    match ec {
+        Handler::DaemonClient(daemon) => {
+            daemon.subscribe(interval).await?;
+            let mut last: Option<Monitor> = None;
+            loop {
+                match daemon.next_event().await? {
+                    DaemonEvent::Monitor(monitor) => {
+                        update(&monitor, true)?;
+                        last = Some(monitor);
+                    }
+                    DaemonEvent::FanMode(fan_mode) => {
+                        if let Some(monitor) = &mut last {
+                            monitor.fan_mode = fan_mode;
+                            update(monitor, false)?;
+                        }
+                    }
+                    DaemonEvent::BatThreshold(_) | DaemonEvent::FanCurves(_) => {}
+                }
+            }
+        }
+        Handler::EmbeddedController(ec) => loop {
+            update(&Monitor::try_new(ec).await?, true)?;
+            tokio::time::sleep(interval).await;
+        },
+    }
+}
+
+/// Redraw machine state in place with statistics until Ctrl-C
+pub async fn watch(ec: &mut Handler, interval: Duration) -> Result<()> {
+    let mut screen = WatchScreen::default();
+    let terminal = std::io::stdout().is_terminal();
+    let res = follow(ec, interval, |monitor, sampled| {
+        if sampled {
+            screen.add(*monitor);
+        } else {
+            screen.monitor = Some(*monitor);
+        }
+        screen.draw(terminal);
+        Ok(())
+    })
+    .await;
+    // Shell prompt starts on its own line after `^C`
+    println!();
+    res
+}
@@ -0,0 +1,216 @@
+use std::{fmt::Display, process::ExitCode};
+
+use clap::ValueEnum;
+
+use crate::{
+    backend::EcTimeout, daemon::error::DaemonError, ec::ReadOnlyModel, traits::WriteFailure,
+};
+
+/// Output format of queries and errors, `--format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    /// Human readable
+    #[default]
+    Text,
+    /// JSON object with unit suffixed field names
+    Json,
+    /// `NAME='value'` lines with the same names as JSON, upper cased. Nested objects are joined with `_`
+    Env,
+}
+
+/// Value of machine readable output
+#[derive(Debug, Clone)]
+pub enum Field {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Null,
+    List(Vec<Field>),
+    Object(Vec<(&'static str, Field)>),
+}
+
+impl From<&str> for Field {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<u64> for Field {
+    fn from(value: u64) -> Self {
+        Self::Num(value)
+    }
+}
+
+impl From<bool> for Field {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl<T: Into<Field>> From<Option<T>> for Field {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// Print query result. `text` is printed as is, `fields` are rendered for the other formats
+pub fn print(format: Format, text: impl Display, fields: Vec<(&'static str, Field)>) {
+    match format {
+        Format::Text => println!("{text}"),
+        Format::Json => println!("{}", json(&Field::Object(fields))),
+        Format::Env => {
+            let mut lines = Vec::new();
+            env(&mut lines, "", &Field::Object(fields));
+            println!("{}", lines.join("\n"));
+        }
+    }
+}
+
+/// Single line JSON of the value
+pub fn json(field: &Field) -> String {
+    match field {
+        Field::Str(value) => json_str(value),
+        Field::Num(value) => value.to_string(),
+        Field::Bool(value) => value.to_string(),
+        Field::Null => "null".to_owned(),
+        Field::List(items) => {
+            let items = items.iter().map(json).collect::<Vec<_>>();
+            format!("[{}]", items.join(","))
+        }
+        Field::Object(fields) => {
+            let fields = fields
+                .iter()
+                .map(|(name, value)| format!("{}:{}", json_str(name), json(value)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        }
+    }
+}
+
+fn json_str(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn env(lines: &mut Vec<String>, prefix: &str, field: &Field) {
+    let name = prefix.to_uppercase();
+    match field {
+        // Quoted, so the output may be sourced by shell
+        Field::Str(value) => lines.push(format!("{name}='{}'", value.replace('\'', r"'\''"))),
+        Field::Num(value) => lines.push(format!("{name}={value}")),
+        Field::Bool(value) => lines.push(format!("{name}={}", u8::from(*value))),
+        Field::Null => lines.push(format!("{name}=")),
+        // Items are numbered like fields of nested object, e.g. `LIST_0`
+        Field::List(items) => {
+            for (i, item) in items.iter().enumerate() {
+                env(lines, &format!("{prefix}_{i}"), item);
+            }
+        }
+        Field::Object(fields) => {
+            for (field_name, value) in fields {
+                let prefix = if prefix.is_empty() {
+                    (*field_name).to_owned()
+                } else {
+                    format!("{prefix}_{field_name}")
+                };
+                env(lines, &prefix, value);
+            }
+        }
+    }
+}
+
+/// Class of error, reported in machine readable errors and as exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Internal,
+    InvalidArgument,
+    PermissionDenied,
+    EcIo,
+    Busy,
+    Unsupported,
+}
+
+impl ErrorClass {
+    pub fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<DaemonError>() {
+                return match err {
+                    DaemonError::InvalidArgument(_) => Self::InvalidArgument,
+                    DaemonError::EcIo(_) => Self::EcIo,
+                    DaemonError::Busy(_) => Self::Busy,
+                    DaemonError::PermissionDenied(_) => Self::PermissionDenied,
+                    DaemonError::Unsupported(_) => Self::Unsupported,
+                    DaemonError::Internal(_) => Self::Internal,
+                };
+            }
+            if cause.is::<EcTimeout>() {
+                return Self::Busy;
+            }
+            if cause.is::<ReadOnlyModel>() {
+                return Self::Unsupported;
+            }
+            if cause.is::<WriteFailure>() {
+                return Self::EcIo;
+            }
+            if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                if err.kind() == std::io::ErrorKind::PermissionDenied {
+                    return Self::PermissionDenied;
+                }
+            }
+        }
+        Self::Internal
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Internal => "internal",
+            Self::InvalidArgument => "invalid_argument",
+            Self::PermissionDenied => "permission_denied",
+            Self::EcIo => "ec_io",
+            Self::Busy => "busy",
+            Self::Unsupported => "unsupported",
+        }
+    }
+
+    /// 2 is shared with usage errors reported by clap
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::InvalidArgument => 2,
+            Self::PermissionDenied => 3,
+            Self::EcIo => 4,
+            Self::Busy => 5,
+            Self::Unsupported => 6,
+        }
+    }
+}
+
+/// Print error in the format and turn it into exit code of its class.
+/// Machine readable errors go to stdout like results, so scripts read only one stream
+pub fn report_error(format: Format, err: &anyhow::Error) -> ExitCode {
+    let class = ErrorClass::of(err);
+    let fields = vec![(
+        "error",
+        Field::Object(vec![
+            ("class", class.name().into()),
+            ("message", format!("{err:#}").as_str().into()),
+        ]),
+    )];
+    match format {
+        Format::Text => eprintln!("Error: {err:?}"),
+        Format::Json | Format::Env => print(format, "", fields),
+    }
+    ExitCode::from(class.exit_code())
+}
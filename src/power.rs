@@ -0,0 +1,212 @@
+#![expect(
+    forbidden_lint_groups,
+    reason = "serde derive allows `unused_extern_crates` forbidden by `rust_2018_idioms`"
+)]
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use rkyv::{rancor::Error as RkyvError, Archive, Deserialize, Serialize};
+
+use crate::{config::parse_str, fan_speed::FanMode, output::Field};
+
+/// File in daemon state directory with power rules set by clients
+pub const POWER_RULES_FILE: &str = "power-rules";
+pub const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+/// Environment variable to override [`POWER_SUPPLY_DIR`], e.g. to fake AC adapter in tests
+pub const POWER_SUPPLY_ENV: &str = "GIGACENTER_POWER_SUPPLY";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+impl PowerSource {
+    /// Power supply directory: [`POWER_SUPPLY_ENV`] or [`POWER_SUPPLY_DIR`]
+    pub fn supply_dir() -> PathBuf {
+        std::env::var_os(POWER_SUPPLY_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(POWER_SUPPLY_DIR))
+    }
+
+    /// Laptop is on AC if any mains power supply is online.
+    /// `None` if there are no mains supplies at all, so power source is unknown
+    pub fn current(supply_dir: &Path) -> Result<Option<Self>> {
+        let entries = std::fs::read_dir(supply_dir)
+            .with_context(|| format!("Read {}", supply_dir.display()))?;
+        let mut source = None;
+        for entry in entries {
+            let path = entry?.path();
+            let Ok(kind) = std::fs::read_to_string(path.join("type")) else {
+                continue;
+            };
+            if kind.trim() != "Mains" {
+                continue;
+            }
+            let online = std::fs::read_to_string(path.join("online"))
+                .with_context(|| format!("Read {}", path.join("online").display()))?;
+            if online.trim() == "1" {
+                return Ok(Some(Self::Ac));
+            }
+            source = Some(Self::Battery);
+        }
+        Ok(source)
+    }
+}
+
+impl Display for PowerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PowerSource::Ac => write!(f, "ac"),
+            PowerSource::Battery => write!(f, "battery"),
+        }
+    }
+}
+
+impl FromStr for PowerSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ac" => Ok(Self::Ac),
+            "battery" => Ok(Self::Battery),
+            _ => bail!("Power source must be `ac` or `battery`"),
+        }
+    }
+}
+
+/// Settings applied when laptop switches to the power source. Unset ones are left as is
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Archive, Serialize, Deserialize, serde::Deserialize,
+)]
+#[serde(deny_unknown_fields, default)]
+pub struct PowerRule {
+    #[serde(deserialize_with = "parse_str")]
+    pub fan_mode: Option<FanMode>,
+    pub bat_threshold: Option<u8>,
+}
+
+impl PowerRule {
+    /// Rules received from clients must be checked before use
+    pub fn check(&self) -> Result<()> {
+        if let Some(fan_mode) = self.fan_mode {
+            if !fan_mode.is_valid() {
+                bail!("Fan mode {fan_mode} can't be set")
+            }
+        }
+        if let Some(threshold) = self.bat_threshold {
+            if !(60..=100).contains(&threshold) {
+                bail!("Battery threshold must be from 60 to 100 (in percent)")
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fan_mode.is_none() && self.bat_threshold.is_none()
+    }
+
+    /// Fields of machine readable output. Unset settings are null
+    pub fn fields(&self) -> Vec<(&'static str, Field)> {
+        vec![
+            (
+                "fan_mode",
+                self.fan_mode
+                    .map(|fan_mode| fan_mode.to_string())
+                    .as_deref()
+                    .into(),
+            ),
+            (
+                "bat_threshold_percent",
+                self.bat_threshold.map(u64::from).into(),
+            ),
+        ]
+    }
+}
+
+impl Display for PowerRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "not set");
+        }
+        let mut parts = Vec::new();
+        if let Some(fan_mode) = self.fan_mode {
+            parts.push(format!("fan_mode={fan_mode}"));
+        }
+        if let Some(threshold) = self.bat_threshold {
+            parts.push(format!("bat_threshold={threshold}"));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Settings applied by daemon on AC/battery switch
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Archive, Serialize, Deserialize, serde::Deserialize,
+)]
+#[serde(deny_unknown_fields, default)]
+pub struct PowerRules {
+    pub ac: PowerRule,
+    pub battery: PowerRule,
+}
+
+impl PowerRules {
+    pub fn check(&self) -> Result<()> {
+        self.ac.check().context("Invalid AC rule")?;
+        self.battery.check().context("Invalid battery rule")?;
+        Ok(())
+    }
+
+    pub fn rule(&self, source: PowerSource) -> &PowerRule {
+        match source {
+            PowerSource::Ac => &self.ac,
+            PowerSource::Battery => &self.battery,
+        }
+    }
+
+    pub fn rule_mut(&mut self, source: PowerSource) -> &mut PowerRule {
+        match source {
+            PowerSource::Ac => &mut self.ac,
+            PowerSource::Battery => &mut self.battery,
+        }
+    }
+
+    /// Load rules saved by daemon. `None` if clients have never set them
+    pub fn load(state_dir: &Path) -> Result<Option<Self>> {
+        let content = match std::fs::read(state_dir.join(POWER_RULES_FILE)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Read power rules file"),
+        };
+        let rules =
+            rkyv::from_bytes::<Self, RkyvError>(&content).context("Parse power rules file")?;
+        Ok(Some(rules))
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(state_dir).context("Create state directory")?;
+        let bytes = rkyv::to_bytes::<RkyvError>(self)?;
+        std::fs::write(state_dir.join(POWER_RULES_FILE), bytes)
+            .context("Write power rules file")?;
+        Ok(())
+    }
+
+    pub fn fields(&self) -> Vec<(&'static str, Field)> {
+        vec![
+            ("ac", Field::Object(self.ac.fields())),
+            ("battery", Field::Object(self.battery.fields())),
+        ]
+    }
+}
+
+impl Display for PowerRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ac: {}", self.ac)?;
+        write!(f, "battery: {}", self.battery)
+    }
+}
@@ -0,0 +1,275 @@
+#![expect(
+    forbidden_lint_groups,
+    reason = "serde derive allows `unused_extern_crates` forbidden by `rust_2018_idioms`"
+)]
+
+use std::{collections::BTreeSet, path::Path, sync::OnceLock};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+
+use crate::{dmi::Dmi, BitPos, ByteOrder, Reg};
+
+/// Directory with user provided profiles. Profile there overrides embedded one with the same id
+pub const PROFILES_DIR: &str = "/etc/gigacenter/models";
+
+/// Profile used for read-only access if the model wasn't detected
+pub const DEFAULT_PROFILE: &str = "aorus-16x-2024";
+
+/// Profiles of known models. Id is the file name without `.toml` extension
+const EMBEDDED_PROFILES: &[(&str, &str)] = &[(
+    "aorus-16x-2024",
+    include_str!("../assets/models/aorus-16x-2024.toml"),
+)];
+
+static ACTIVE_PROFILE: OnceLock<ActiveProfile> = OnceLock::new();
+
+#[derive(Debug)]
+struct ActiveProfile {
+    profile: Profile,
+    /// Writes to EC are allowed only for detected or explicitly forced models
+    writable: bool,
+}
+
+/// Single bit of EC register
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BitReg {
+    pub reg: Reg,
+    pub bit: BitPos,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanModeRegs {
+    pub eco: BitReg,
+    pub power: BitReg,
+    pub custom: BitReg,
+    pub fixed: BitReg,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedSpeedRegs {
+    pub cpu_fan: Reg,
+    pub gpu_fan: Reg,
+    /// Raw value of the slowest fixed speed
+    pub min_value: u8,
+    /// Raw value of the fastest fixed speed
+    pub max_value: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanSpeedRegs {
+    pub cpu: Reg,
+    pub gpu: Reg,
+    pub byte_order: ByteOrder,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TempRegs {
+    pub cpu: Reg,
+    pub gpu: Reg,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatThresholdRegs {
+    pub custom_mode: BitReg,
+    pub toggle_custom: BitReg,
+    pub value: Reg,
+}
+
+/// DMI values the profile is detected by. Every non-empty field must match.
+/// Value ending with `*` matches any value with such prefix
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DmiMatch {
+    #[serde(default)]
+    pub sys_vendor: Vec<String>,
+    #[serde(default)]
+    pub product_name: Vec<String>,
+    #[serde(default)]
+    pub board_name: Vec<String>,
+    #[serde(default)]
+    pub bios_version: Vec<String>,
+}
+
+impl DmiMatch {
+    pub fn matches(&self, dmi: &Dmi) -> bool {
+        let fields = [
+            (&self.sys_vendor, &dmi.sys_vendor),
+            (&self.product_name, &dmi.product_name),
+            (&self.board_name, &dmi.board_name),
+            (&self.bios_version, &dmi.bios_version),
+        ];
+        // Profile without match rules is never detected automatically
+        if fields.iter().all(|(patterns, _)| patterns.is_empty()) {
+            return false;
+        }
+        fields.iter().all(|(patterns, value)| {
+            patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|pattern| match pattern.strip_suffix('*') {
+                        Some(prefix) => value.starts_with(prefix),
+                        None => pattern == *value,
+                    })
+        })
+    }
+}
+
+/// Declarative EC register map of a laptop model
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// File name of the profile without extension
+    #[serde(skip)]
+    pub id: String,
+    /// Human readable model name
+    pub name: String,
+    pub fan_mode: FanModeRegs,
+    pub fixed_speed: FixedSpeedRegs,
+    pub fan_speed: FanSpeedRegs,
+    pub temp: TempRegs,
+    pub bat_threshold: BatThresholdRegs,
+    #[serde(rename = "match", default)]
+    pub dmi_match: DmiMatch,
+}
+
+impl Profile {
+    pub fn parse(id: &str, content: &str) -> Result<Self> {
+        let mut profile = toml::from_str::<Self>(content)
+            .with_context(|| format!("Parse `{id}` model profile"))?;
+        if profile.fixed_speed.min_value >= profile.fixed_speed.max_value {
+            bail!("Model profile `{id}`: fixed speed min_value must be less than max_value")
+        }
+        if profile.fan_speed.cpu == Reg::MAX || profile.fan_speed.gpu == Reg::MAX {
+            bail!("Model profile `{id}`: fan speeds are 16-bit, so their registers must be below 0xff")
+        }
+        profile.id = id.to_owned();
+        Ok(profile)
+    }
+
+    /// Load profile from [`PROFILES_DIR`] or from embedded ones
+    pub fn load(id: &str) -> Result<Self> {
+        let path = Path::new(PROFILES_DIR).join(format!("{id}.toml"));
+        match std::fs::read_to_string(&path) {
+            Ok(content) => return Self::parse(id, &content),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_context(|| format!("Read {}", path.display())),
+        }
+        Self::embedded(id)
+    }
+
+    /// All known profiles: embedded and from [`PROFILES_DIR`], sorted by id, so detection doesn't
+    /// depend on directory order. Broken user profiles are skipped
+    pub fn all() -> Vec<Self> {
+        let mut profiles = vec![];
+        if let Ok(entries) = std::fs::read_dir(PROFILES_DIR) {
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.extension().is_none_or(|ext| ext != "toml") {
+                    continue;
+                }
+                let Some(id) = path.file_stem().map(|id| id.to_string_lossy()) else {
+                    continue;
+                };
+                let profile = std::fs::read_to_string(&path)
+                    .context("Read profile file")
+                    .and_then(|content| Self::parse(&id, &content));
+                match profile {
+                    Ok(profile) => profiles.push(profile),
+                    Err(err) => warn!("Skip {}: {err:#}", path.display()),
+                }
+            }
+        }
+        for (id, _) in EMBEDDED_PROFILES {
+            if profiles.iter().all(|profile| profile.id != *id) {
+                // SAFETY: embedded profiles are checked at development time
+                profiles.push(Self::embedded(id).unwrap());
+            }
+        }
+        profiles.sort_by(|a, b| a.id.cmp(&b.id));
+        profiles
+    }
+
+    /// Find profile matching the laptop DMI. The first one by id wins if several match
+    pub fn detect(dmi: &Dmi) -> Option<Self> {
+        let mut matching = Self::all()
+            .into_iter()
+            .filter(|profile| profile.dmi_match.matches(dmi));
+        let profile = matching.next()?;
+        let others = matching
+            .map(|profile| format!("`{}`", profile.id))
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            warn!(
+                "Model profiles {} match this laptop too, `{}` is used. Narrow their [match] sections or pick one with `--force-model` or `model` in config",
+                others.join(", "),
+                profile.id
+            );
+        }
+        Some(profile)
+    }
+
+    /// Registers needed to show machine state. Daemon lets anyone read them
+    pub fn readable_regs(&self) -> BTreeSet<Reg> {
+        let fan_mode = &self.fan_mode;
+        let bat_threshold = &self.bat_threshold;
+        let mut regs = BTreeSet::from([
+            fan_mode.eco.reg,
+            fan_mode.power.reg,
+            fan_mode.custom.reg,
+            fan_mode.fixed.reg,
+            self.fixed_speed.cpu_fan,
+            self.fixed_speed.gpu_fan,
+            self.temp.cpu,
+            self.temp.gpu,
+            bat_threshold.custom_mode.reg,
+            bat_threshold.toggle_custom.reg,
+            bat_threshold.value,
+        ]);
+        // Fan speeds are 16-bit
+        for reg in [self.fan_speed.cpu, self.fan_speed.gpu] {
+            _ = regs.insert(reg);
+            _ = regs.insert(reg + 1);
+        }
+        regs
+    }
+
+    fn embedded(id: &str) -> Result<Self> {
+        let (_, content) = EMBEDDED_PROFILES
+            .iter()
+            .find(|(embedded_id, _)| *embedded_id == id)
+            .ok_or_else(|| anyhow!("Unknown model profile `{id}`"))?;
+        Self::parse(id, content)
+    }
+}
+
+fn active_profile() -> &'static ActiveProfile {
+    ACTIVE_PROFILE.get_or_init(|| ActiveProfile {
+        // SAFETY: embedded profiles are checked at development time
+        profile: Profile::embedded(DEFAULT_PROFILE).unwrap(),
+        writable: false,
+    })
+}
+
+/// Profile of the current laptop model. Read-only [`DEFAULT_PROFILE`] if none was set with [`set_profile`]
+pub fn profile() -> &'static Profile {
+    &active_profile().profile
+}
+
+/// Whether writes to EC are allowed for the current model
+pub fn is_writable() -> bool {
+    active_profile().writable
+}
+
+/// Must be called before any EC access
+pub fn set_profile(profile: Profile, writable: bool) -> Result<()> {
+    ACTIVE_PROFILE
+        .set(ActiveProfile { profile, writable })
+        .map_err(|_| anyhow!("Model profile is already set"))
+}
@@ -0,0 +1,157 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Result};
+use log::warn;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::{
+    daemon::{codec::DaemonReq, error::DaemonError},
+    output::Field,
+    traits::{ECHandler, InvokeDaemon, WriteEC},
+    BitPos, BitState, ByteOrder, RWData, Reg,
+};
+
+/// Single register or bit written as is by `gigacenter ec write`
+#[derive(Debug, Clone, Copy, Archive, Serialize, Deserialize)]
+pub struct RawWrite(pub RWData);
+
+impl RawWrite {
+    pub fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    pub fn reg(self) -> Reg {
+        match self.0 {
+            RWData::U1 { reg, .. } | RWData::U8 { reg, .. } | RWData::U16 { reg, .. } => reg,
+        }
+    }
+
+    /// The same register with zeroed value, ready to be read
+    pub fn to_read(self) -> RWData {
+        match self.0 {
+            RWData::U1 { reg, pos, .. } => RWData::U1 {
+                reg,
+                pos,
+                state: BitState::Disabled,
+            },
+            RWData::U8 { reg, .. } => RWData::U8 { reg, value: 0 },
+            RWData::U16 {
+                reg, byte_order, ..
+            } => RWData::U16 {
+                reg,
+                value: 0,
+                byte_order,
+            },
+        }
+    }
+}
+
+impl WriteEC for RawWrite {
+    fn data_to_write(&self) -> Vec<RWData> {
+        vec![self.0]
+    }
+}
+
+impl InvokeDaemon for RawWrite {
+    fn daemon_action(&self) -> DaemonReq {
+        DaemonReq::WriteRaw(*self)
+    }
+}
+
+/// Width of raw register value
+#[derive(Debug, Clone, Copy)]
+pub enum RawWidth {
+    Bit(BitPos),
+    U8,
+    U16(ByteOrder),
+}
+
+impl RawWidth {
+    pub fn data(self, reg: Reg) -> RWData {
+        match self {
+            RawWidth::Bit(pos) => RWData::U1 {
+                reg,
+                pos,
+                state: BitState::Disabled,
+            },
+            RawWidth::U8 => RWData::U8 { reg, value: 0 },
+            RawWidth::U16(byte_order) => RWData::U16 {
+                reg,
+                value: 0,
+                byte_order,
+            },
+        }
+    }
+}
+
+/// Value of raw register as it's printed by `gigacenter ec read`
+#[derive(Debug, Clone, Copy)]
+pub struct RawValue(pub RWData);
+
+impl RawValue {
+    /// Fields of machine readable output, values are decimal
+    pub fn fields(&self) -> Vec<(&'static str, Field)> {
+        let (reg, value) = match self.0 {
+            RWData::U1 { reg, state, .. } => (reg, state as u64),
+            RWData::U8 { reg, value } => (reg, value.into()),
+            RWData::U16 { reg, value, .. } => (reg, value.into()),
+        };
+        vec![("reg", u64::from(reg).into()), ("value", value.into())]
+    }
+}
+
+impl Display for RawValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            RWData::U1 { state, .. } => write!(f, "{state}"),
+            RWData::U8 { value, .. } => write!(f, "{value:#04x}"),
+            RWData::U16 { value, .. } => write!(f, "{value:#06x}"),
+        }
+    }
+}
+
+pub async fn read(ec: &mut impl ECHandler, reg: Reg, width: RawWidth) -> Result<RawValue> {
+    let data = width.data(reg);
+    if !data.is_valid() {
+        // Classified the same way as the daemon rejecting it
+        return Err(DaemonError::InvalidArgument(RWData::INVALID.to_owned()).into());
+    }
+    let data = ec.read_values(vec![data]).await?;
+    let Some(data) = data.first() else {
+        bail!("No value read from register {reg:#04x}")
+    };
+    Ok(RawValue(*data))
+}
+
+/// Write register and print its previous value, so the write can be reverted
+pub async fn write(ec: &mut impl ECHandler, data: RawWrite) -> Result<()> {
+    if !data.is_valid() {
+        return Err(DaemonError::InvalidArgument(RWData::INVALID.to_owned()).into());
+    }
+    let prev = ec.read_values(vec![data.to_read()]).await?;
+    let Some(prev) = prev.first().copied() else {
+        bail!("Failed to read previous value")
+    };
+    _ = ec.write_data(&data).await?;
+    let reg = data.reg();
+    let revert = match prev {
+        RWData::U1 { pos, state, .. } => format!("{reg:#04x} {state} --bit {}", *pos),
+        RWData::U8 { value, .. } => format!("{reg:#04x} {value:#04x}"),
+        RWData::U16 {
+            value, byte_order, ..
+        } => match byte_order {
+            ByteOrder::Big => format!("{reg:#04x} {value:#06x} --u16"),
+            ByteOrder::Little => format!("{reg:#04x} {value:#06x} --u16 --little-endian"),
+        },
+    };
+    warn!(
+        "Register {reg:#04x} changed from {} to {}",
+        RawValue(prev),
+        RawValue(data.0)
+    );
+    eprintln!(
+        "Previous value: {}. Revert with `gigacenter ec write {revert} --i-know-what-im-doing`",
+        RawValue(prev)
+    );
+    Ok(())
+}
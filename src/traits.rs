@@ -2,11 +2,12 @@ use crate::{
     common::Handler,
     daemon::{client::DaemonClient, codec::DaemonReq},
     ec::EmbeddedController,
-    RWData,
+    RWData, Reg,
 };
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
 use rkyv::{Archive, Deserialize, Serialize};
+use std::fmt::Display;
 
 pub trait WriteEC: Sync + Send {
     /// A series of data to be written to EC
@@ -27,12 +28,82 @@ pub trait InvokeDaemon {
 pub enum WriteResult {
     Done,
     Busy,
+    /// Write didn't stick. Sent by daemon, clients turn it into [`WriteFailure`] error
+    Failed(WriteFailure),
 }
 
+/// Register whose written bits differ from the expected ones after write
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+pub struct FailedReg {
+    pub reg: Reg,
+    /// Bits written by the batch
+    pub mask: u8,
+    pub expected: u8,
+    /// `None` if the register couldn't be written or read back
+    pub actual: Option<u8>,
+}
+
+impl Display for FailedReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let expected = self.expected & self.mask;
+        match self.actual {
+            Some(actual) => write!(
+                f,
+                "{:#04x} (expected {expected:#04x}, got {:#04x} in bits {:#04x})",
+                self.reg,
+                actual & self.mask,
+                self.mask
+            ),
+            None => write!(
+                f,
+                "{:#04x} (expected {expected:#04x} in bits {:#04x}, access failed)",
+                self.reg, self.mask
+            ),
+        }
+    }
+}
+
+/// Write batch failed verification or was interrupted by error. Written bits are restored
+/// to their original values if `rolled_back`
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+pub struct WriteFailure {
+    pub failed: Vec<FailedReg>,
+    pub rolled_back: bool,
+    /// Error interrupted the batch
+    pub error: Option<String>,
+}
+
+impl Display for WriteFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Write to EC failed")?;
+        if let Some(error) = &self.error {
+            write!(f, " ({error})")?;
+        }
+        let failed = self
+            .failed
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        write!(f, ". Failed registers: {}", failed.join(", "))?;
+        if self.rolled_back {
+            write!(f, ". Original values restored")
+        } else {
+            write!(
+                f,
+                ". Failed to restore original values, check registers with `gigacenter ec dump`"
+            )
+        }
+    }
+}
+
+impl std::error::Error for WriteFailure {}
+
 /// Trait to manipulate EC data
 #[enum_dispatch]
 #[expect(async_fn_in_trait)]
 pub trait ECHandler: Sized {
     async fn read_data<T: ReadEC>(&mut self) -> Result<Vec<RWData>>;
+    /// Read data not known at compile time, e.g. raw registers
+    async fn read_values(&mut self, data: Vec<RWData>) -> Result<Vec<RWData>>;
     async fn write_data(&mut self, data: &(impl WriteEC + InvokeDaemon)) -> Result<WriteResult>;
 }
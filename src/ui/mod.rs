@@ -12,8 +12,11 @@ use slint::{Brush, Color, ComponentHandle, ToSharedString, Weak};
 use tokio::sync::Mutex;
 
 use crate::{
-    bat::BatThreshold, daemon::client::DaemonClient, fan_speed, monitor::Monitor as Monitor_,
-    traits::ECHandler, WRITE_TIMEOUT_MS,
+    bat::BatThreshold,
+    daemon::{client::DaemonClient, subscribe::DaemonEvent},
+    fan_speed,
+    traits::ECHandler,
+    WRITE_TIMEOUT_MS,
 };
 
 impl From<fan_speed::FanMode> for FanMode {
@@ -23,6 +26,7 @@ impl From<fan_speed::FanMode> for FanMode {
             fan_speed::FanMode::Eco => FanMode::Eco,
             fan_speed::FanMode::Power => FanMode::Power,
             fan_speed::FanMode::Turbo => FanMode::Turbo,
+            fan_speed::FanMode::Fixed { .. } => FanMode::Fixed,
             fan_speed::FanMode::Unsupported => FanMode::Unsupported,
         }
     }
@@ -35,7 +39,8 @@ impl From<FanMode> for fan_speed::FanMode {
             FanMode::Eco => fan_speed::FanMode::Eco,
             FanMode::Power => fan_speed::FanMode::Power,
             FanMode::Turbo => fan_speed::FanMode::Turbo,
-            FanMode::Unsupported => fan_speed::FanMode::Unsupported,
+            // Fixed speeds are set by `set_fixed_speed` callback
+            FanMode::Fixed | FanMode::Unsupported => fan_speed::FanMode::Unsupported,
         }
     }
 }
@@ -62,14 +67,28 @@ pub async fn gui() -> Result<()> {
         app.global::<State<'_>>().set_config(app_config);
     }
 
+    // Writes go through `daemon`, state is pushed to a dedicated connection
+    let mut subscriber = DaemonClient::connect().await?;
+    subscriber.subscribe(Duration::from_secs(1)).await?;
     let cloned_app = app.as_weak();
     _ = tokio::spawn(async move {
         loop {
-            let mut daemon = daemon.lock().await;
-            let monitor = Monitor_::try_new(&mut *daemon).await?;
-            drop(daemon);
+            let monitor = match subscriber.next_event().await? {
+                DaemonEvent::Monitor(monitor) => monitor,
+                // Mode set by another client is shown without waiting for the next snapshot
+                DaemonEvent::FanMode(fan_mode) => {
+                    cloned_app.upgrade_in_event_loop(move |app| {
+                        let mut monitor = app.get_monitor();
+                        monitor.fan_mode = fan_mode.into();
+                        app.set_monitor(monitor);
+                    })?;
+                    continue;
+                }
+                DaemonEvent::BatThreshold(_) | DaemonEvent::FanCurves(_) => continue,
+            };
             cloned_app.upgrade_in_event_loop(move |app| {
-                let prev_threshold = app.get_monitor().bat_threshold;
+                let prev_monitor = app.get_monitor();
+                let prev_threshold = prev_monitor.bat_threshold;
                 if prev_threshold != *monitor.bat_threshold as i32 {
                     app.global::<State<'_>>().set_threshold_value(
                         (*monitor.bat_threshold as i32)
@@ -77,6 +96,11 @@ pub async fn gui() -> Result<()> {
                             .to_shared_string(),
                     );
                 }
+                // Keep last known fixed speeds, so sliders don't jump when mode isn't fixed
+                let (cpu_fixed_speed, gpu_fixed_speed) = match monitor.fan_mode {
+                    fan_speed::FanMode::Fixed { cpu, gpu } => (cpu as i32, gpu as i32),
+                    _ => (prev_monitor.cpu_fixed_speed, prev_monitor.gpu_fixed_speed),
+                };
                 app.set_monitor(slint_generatedApp::Monitor {
                     bat_threshold: *monitor.bat_threshold as i32,
                     cpu_fan_speed: *monitor.cpu_fan_speed as i32,
@@ -84,9 +108,10 @@ pub async fn gui() -> Result<()> {
                     fan_mode: monitor.fan_mode.into(),
                     gpu_fan_speed: *monitor.gpu_fan_speed as i32,
                     gpu_temp: *monitor.gpu_temp as i32,
+                    cpu_fixed_speed,
+                    gpu_fixed_speed,
                 });
             })?;
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
         #[allow(unreachable_code)]
         Ok::<_, anyhow::Error>(())
@@ -112,10 +137,29 @@ pub async fn gui() -> Result<()> {
         move |fan_mode| {
             block_user_input(weak.clone());
             let fm = fan_speed::FanMode::from(fan_mode);
+            let weak = weak.clone();
             _ = tokio::spawn({
                 async move {
                     let mut daemon = daemon.lock().await;
-                    _ = daemon.write_data(&fm).await;
+                    show_error(weak, daemon.write_data(&fm).await);
+                }
+            });
+        }
+    });
+
+    app.global::<State<'_>>().on_set_fixed_speed({
+        let weak = app.as_weak();
+        move |cpu, gpu| {
+            block_user_input(weak.clone());
+            let fm = fan_speed::FanMode::Fixed {
+                cpu: cpu.clamp(0, 100) as u8,
+                gpu: gpu.clamp(0, 100) as u8,
+            };
+            let weak = weak.clone();
+            _ = tokio::spawn({
+                async move {
+                    let mut daemon = daemon.lock().await;
+                    show_error(weak, daemon.write_data(&fm).await);
                 }
             });
         }
@@ -142,10 +186,11 @@ pub async fn gui() -> Result<()> {
             app.global::<State<'_>>()
                 .set_threshold_value(threshold.to_string().to_shared_string());
 
+            let weak = app.as_weak();
             _ = tokio::spawn({
                 async move {
                     let mut daemon = daemon.lock().await;
-                    _ = daemon.write_data(&BatThreshold::new(threshold)).await;
+                    show_error(weak, daemon.write_data(&BatThreshold::new(threshold)).await);
                 }
             });
         }
@@ -158,6 +203,17 @@ pub async fn gui() -> Result<()> {
     Ok(app.run()?)
 }
 
+/// Show why request to daemon failed, e.g. invalid value or EC timeout
+fn show_error<T>(weak: Weak<App>, res: Result<T>) {
+    let Err(err) = res else {
+        return;
+    };
+    let msg = format!("{err:#}");
+    _ = weak.upgrade_in_event_loop(move |app| {
+        app.global::<State<'_>>().set_error(msg.to_shared_string());
+    });
+}
+
 fn block_user_input(weak: Weak<App>) {
     weak.upgrade_in_event_loop(|app| {
         app.global::<State<'_>>().set_blocked(true);
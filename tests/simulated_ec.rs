@@ -0,0 +1,1380 @@
+//! End-to-end tests running gigacenter against simulated EC. Need neither root nor Gigabyte laptop
+use std::{
+    io::{Read as _, Write as _},
+    os::{
+        fd::AsRawFd as _,
+        unix::{
+            fs::{FileExt as _, MetadataExt as _},
+            net::{UnixDatagram, UnixListener, UnixStream},
+            process::CommandExt as _,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::mpsc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_gigacenter");
+const MODEL: &str = "aorus-16x-2024";
+
+/// Fresh directory for test files
+fn test_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// EC image with zeroed registers except the given ones
+fn ec_image(dir: &Path, regs: &[(usize, u8)]) -> PathBuf {
+    let mut image = vec![0; 256];
+    for (reg, value) in regs {
+        image[*reg] = *value;
+    }
+    let path = dir.join("ec.img");
+    std::fs::write(&path, image).unwrap();
+    path
+}
+
+fn gigacenter(image: &Path, args: &[&str]) -> Output {
+    let output = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(image)
+        .args(["--force-model", MODEL])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "gigacenter {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+fn reg(image: &Path, reg: usize) -> u8 {
+    std::fs::read(image).unwrap()[reg]
+}
+
+/// Change a single register in place, so writes of running gigacenter to other registers aren't lost
+fn set_reg(image: &Path, reg: usize, value: u8) {
+    let file = std::fs::OpenOptions::new().write(true).open(image).unwrap();
+    file.write_all_at(&[value], reg as u64).unwrap();
+}
+
+#[test]
+fn fan_mode_round_trip() {
+    let dir = test_dir("fan_mode_round_trip");
+    let image = ec_image(&dir, &[]);
+
+    assert_eq!(stdout(&gigacenter(&image, &["-f"])), "normal");
+    for mode in ["eco", "power", "turbo", "fixed:40,60", "normal"] {
+        _ = gigacenter(&image, &["-f", mode]);
+        assert_eq!(stdout(&gigacenter(&image, &["-f"])), mode);
+    }
+}
+
+#[test]
+fn fixed_fan_mode_registers() {
+    let dir = test_dir("fixed_fan_mode_registers");
+    let image = ec_image(&dir, &[]);
+
+    _ = gigacenter(&image, &["-f", "fixed:0,100"]);
+    assert_eq!(reg(&image, 0x0D) & 1 << 7, 1 << 7);
+    assert_eq!(reg(&image, 0x06) & 1 << 4, 1 << 4);
+    assert_eq!(reg(&image, 0xB0), 0x39);
+    assert_eq!(reg(&image, 0xB1), 0xE5);
+}
+
+#[test]
+fn bat_threshold_round_trip() {
+    let dir = test_dir("bat_threshold_round_trip");
+    let image = ec_image(&dir, &[]);
+
+    assert_eq!(stdout(&gigacenter(&image, &["-b"])), "100");
+    _ = gigacenter(&image, &["-b", "80"]);
+    assert_eq!(stdout(&gigacenter(&image, &["-b"])), "80");
+    assert_eq!(reg(&image, 0xA9), 80);
+    _ = gigacenter(&image, &["-b", "100"]);
+    assert_eq!(stdout(&gigacenter(&image, &["-b"])), "100");
+}
+
+#[test]
+fn monitor_shows_sensors() {
+    let dir = test_dir("monitor_shows_sensors");
+    let image = ec_image(
+        &dir,
+        &[
+            (0x60, 55),
+            (0x61, 48),
+            (0xFC, 0x0B),
+            (0xFD, 0xB8),
+            (0xFE, 0x0A),
+            (0xFF, 0x28),
+        ],
+    );
+
+    let show = stdout(&gigacenter(&image, &["-s"]));
+    assert!(show.contains("- CPU Temp: 55"), "{show}");
+    assert!(show.contains("- GPU Temp: 48"), "{show}");
+    assert!(show.contains("- CPU fan speed: 3000"), "{show}");
+    assert!(show.contains("- GPU fan speed: 2600"), "{show}");
+    assert!(show.contains("- Fan mode: normal"), "{show}");
+}
+
+#[test]
+fn unknown_model_is_read_only() {
+    let dir = test_dir("unknown_model_is_read_only");
+    let image = ec_image(&dir, &[]);
+
+    let output = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["-f", "eco"])
+        .output()
+        .unwrap();
+    // Sandbox running tests is never detected as a known Gigabyte laptop
+    assert!(!output.status.success());
+    assert_eq!(reg(&image, 0x08), 0);
+}
+
+/// Kill daemon even if test panics
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        _ = self.0.kill();
+        _ = self.0.wait();
+    }
+}
+
+/// Simulated daemon configured by `config`. Returns once its socket appears, so settings are restored by then.
+/// Power supplies are read from `power_supply` directory next to the config
+fn spawn_daemon(image: &Path, config: &Path, socket: &Path) -> Daemon {
+    spawn_daemon_env(image, config, socket, &[])
+}
+
+/// Lines printed by the child, read in background, so tests may wait for them with timeout
+fn spawn_line_reader(child: &mut Child) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    let output = child.stdout.take().unwrap();
+    _ = std::thread::spawn(move || {
+        for line in std::io::BufRead::lines(std::io::BufReader::new(output)) {
+            let Ok(line) = line else {
+                break;
+            };
+            _ = tx.send(line);
+        }
+    });
+    rx
+}
+
+/// Same as [`spawn_daemon`] with extra environment variables
+fn spawn_daemon_env(
+    image: &Path,
+    config: &Path,
+    socket: &Path,
+    envs: &[(&str, &std::ffi::OsStr)],
+) -> Daemon {
+    let daemon = Daemon(
+        Command::new(BIN)
+            .envs(envs.iter().copied())
+            .env("GIGACENTER_CONFIG", config)
+            .env(
+                "GIGACENTER_POWER_SUPPLY",
+                config.with_file_name("power_supply"),
+            )
+            .env_remove("GIGACENTER_SOCKET")
+            .env_remove("GIGACENTER_EC_IMAGE")
+            .arg("--ec-image")
+            .arg(image)
+            .args(["--daemon", "run"])
+            .spawn()
+            .unwrap(),
+    );
+    let start = Instant::now();
+    while !socket.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Daemon didn't start"
+        );
+        sleep(Duration::from_millis(50));
+    }
+    daemon
+}
+
+/// Client takes socket path from the daemon config, but neither EC image nor model
+fn client(config: &Path, args: &[&str]) -> Output {
+    let output = Command::new(BIN)
+        .env("GIGACENTER_CONFIG", config)
+        .env_remove("GIGACENTER_SOCKET")
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "gigacenter {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn daemon_config(dir: &Path, extra: &str) -> (PathBuf, PathBuf) {
+    let socket = dir.join("gigacenter.sock");
+    let config = dir.join("config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "model = \"{MODEL}\"\nstate_dir = {:?}\n{extra}\n[socket]\npath = {socket:?}\n",
+            dir.join("state")
+        ),
+    )
+    .unwrap();
+    (config, socket)
+}
+
+#[test]
+fn daemon_round_trip() {
+    let dir = test_dir("daemon_round_trip");
+    let image = ec_image(&dir, &[(0x60, 61)]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    let client = |args: &[&str]| client(&config, args);
+
+    _ = client(&["-f", "power"]);
+    assert_eq!(stdout(&client(&["-f"])), "power");
+    assert_eq!(reg(&image, 0x0C) & 1 << 4, 1 << 4);
+
+    _ = client(&["-b", "75"]);
+    assert_eq!(stdout(&client(&["-b"])), "75");
+
+    let show = stdout(&client(&["-s"]));
+    assert!(show.contains("- CPU Temp: 61"), "{show}");
+
+    // Daemon owner is allowed to write raw registers
+    _ = client(&["ec", "write", "0x50", "0x42", "--i-know-what-im-doing"]);
+    assert_eq!(stdout(&client(&["ec", "read", "0x50"])), "0x42");
+    assert_eq!(reg(&image, 0x50), 0x42);
+}
+
+#[test]
+fn ec_watch_appends_plain_snapshots() {
+    let dir = test_dir("ec_watch_appends_plain_snapshots");
+    let image = ec_image(&dir, &[(0x60, 0x37)]);
+    let mut watch = Daemon(
+        Command::new(BIN)
+            .env_remove("GIGACENTER_EC_IMAGE")
+            .arg("--ec-image")
+            .arg(&image)
+            .args([
+                "--force-model",
+                MODEL,
+                "ec",
+                "dump",
+                "--watch",
+                "--interval",
+                "100",
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let rx = spawn_line_reader(&mut watch.0);
+    let mut output = vec![];
+    let mut wait_line = |expected: &str| {
+        while !output
+            .iter()
+            .any(|line: &String| line.starts_with(expected))
+        {
+            output.push(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+        }
+    };
+
+    wait_line("60:  37");
+    set_reg(&image, 0x60, 0x38);
+    wait_line("0x60: 0x37 -> 0x38");
+    // Piped output has no screen clearing or colors
+    assert!(
+        output.iter().all(|line| !line.contains('\x1b')),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn ec_dump_and_diff() {
+    let dir = test_dir("ec_dump_and_diff");
+    let image = ec_image(&dir, &[(0x60, 0x37)]);
+    let (before, after) = (dir.join("before.txt"), dir.join("after.txt"));
+
+    let dump = stdout(&gigacenter(
+        &image,
+        &["ec", "dump", "--output", before.to_str().unwrap()],
+    ));
+    assert!(dump.contains("60:  37 00"), "{dump}");
+    _ = gigacenter(&image, &["-f", "power"]);
+    _ = gigacenter(&image, &["ec", "dump", "--output", after.to_str().unwrap()]);
+
+    let diff = stdout(&gigacenter(
+        &image,
+        &[
+            "ec",
+            "diff",
+            before.to_str().unwrap(),
+            after.to_str().unwrap(),
+        ],
+    ));
+    assert!(
+        diff.ends_with("0x0c: 0x00 -> 0x10 (bit 4: 0 -> 1)"),
+        "{diff}"
+    );
+    // Raw image is accepted as well
+    let diff = stdout(&gigacenter(
+        &image,
+        &[
+            "ec",
+            "diff",
+            after.to_str().unwrap(),
+            image.to_str().unwrap(),
+        ],
+    ));
+    assert_eq!(diff, "Dumps are identical");
+}
+
+#[test]
+fn raw_register_read_write() {
+    let dir = test_dir("raw_register_read_write");
+    let image = ec_image(&dir, &[(0x30, 0x12), (0x31, 0x34)]);
+
+    assert_eq!(stdout(&gigacenter(&image, &["ec", "read", "0x30"])), "0x12");
+    assert_eq!(
+        stdout(&gigacenter(&image, &["ec", "read", "0x30", "--u16"])),
+        "0x1234"
+    );
+    assert_eq!(
+        stdout(&gigacenter(&image, &["ec", "read", "0x30", "--bit", "1"])),
+        "1"
+    );
+
+    let output = gigacenter(
+        &image,
+        &["ec", "write", "0x30", "0xff", "--i-know-what-im-doing"],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("gigacenter ec write 0x30 0x12 --i-know-what-im-doing"),
+        "{stderr}"
+    );
+    _ = gigacenter(
+        &image,
+        &[
+            "ec",
+            "write",
+            "0x30",
+            "0",
+            "--bit",
+            "0",
+            "--i-know-what-im-doing",
+        ],
+    );
+    assert_eq!(reg(&image, 0x30), 0xfe);
+
+    // Writes must be confirmed
+    let output = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["--force-model", MODEL, "ec", "write", "0x30", "0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(reg(&image, 0x30), 0xfe);
+}
+
+#[test]
+fn raw_u16_byte_order() {
+    let dir = test_dir("raw_u16_byte_order");
+    let image = ec_image(&dir, &[]);
+
+    _ = gigacenter(
+        &image,
+        &[
+            "ec",
+            "write",
+            "0x40",
+            "0x1234",
+            "--u16",
+            "--i-know-what-im-doing",
+        ],
+    );
+    assert_eq!((reg(&image, 0x40), reg(&image, 0x41)), (0x12, 0x34));
+    _ = gigacenter(
+        &image,
+        &[
+            "ec",
+            "write",
+            "0x42",
+            "0x1234",
+            "--u16",
+            "--little-endian",
+            "--i-know-what-im-doing",
+        ],
+    );
+    assert_eq!((reg(&image, 0x42), reg(&image, 0x43)), (0x34, 0x12));
+    assert_eq!(
+        stdout(&gigacenter(
+            &image,
+            &["ec", "read", "0x42", "--u16", "--little-endian"]
+        )),
+        "0x1234"
+    );
+
+    // 16-bit value at the last register would wrap to 0x00
+    set_reg(&image, 0x00, 0x77);
+    for args in [
+        &[
+            "ec",
+            "write",
+            "0xff",
+            "0x1234",
+            "--u16",
+            "--i-know-what-im-doing",
+        ][..],
+        &["ec", "read", "0xff", "--u16"],
+    ] {
+        let output = Command::new(BIN)
+            .env_remove("GIGACENTER_EC_IMAGE")
+            .arg("--ec-image")
+            .arg(&image)
+            .args(["--force-model", MODEL])
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Invalid argument"), "{stderr}");
+    }
+    assert_eq!((reg(&image, 0xff), reg(&image, 0x00)), (0, 0x77));
+}
+
+#[test]
+fn daemon_config_defaults() {
+    let dir = test_dir("daemon_config_defaults");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "fan_mode = \"eco\"\nbat_threshold = 70");
+
+    assert_eq!(
+        stdout(&gigacenter(
+            &image,
+            &["config", "check", config.to_str().unwrap()]
+        )),
+        format!("Config {} is valid", config.display())
+    );
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    assert_eq!(stdout(&client(&config, &["-f"])), "eco");
+    assert_eq!(stdout(&client(&config, &["-b"])), "70");
+}
+
+#[test]
+fn invalid_config_is_rejected() {
+    let dir = test_dir("invalid_config_is_rejected");
+    let image = ec_image(&dir, &[]);
+    let (config, _) = daemon_config(&dir, "bat_threshold = 20");
+
+    let output = Command::new(BIN)
+        .env("GIGACENTER_CONFIG", &config)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["--daemon", "run"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("bat_threshold must be from 60 to 100"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn daemon_restores_settings() {
+    let dir = test_dir("daemon_restores_settings");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "fan_mode = \"eco\"");
+
+    let daemon = spawn_daemon(&image, &config, &socket);
+    _ = client(&config, &["-f", "power"]);
+    _ = client(&config, &["-b", "75"]);
+    drop(daemon);
+
+    // EC reset to defaults, e.g. after reboot. Settings applied by client win over config
+    _ = ec_image(&dir, &[]);
+    std::fs::remove_file(&socket).unwrap();
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    assert_eq!(stdout(&client(&config, &["-f"])), "power");
+    assert_eq!(stdout(&client(&config, &["-b"])), "75");
+}
+
+#[test]
+fn daemon_restores_settings_after_resume() {
+    let dir = test_dir("daemon_restores_settings_after_resume");
+    let Some((_bus_daemon, address)) = private_bus(&dir) else {
+        return skip_without_dbus("daemon_restores_settings_after_resume");
+    };
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "fan_mode = \"eco\"\nbat_threshold = 80");
+
+    // Fake logind is on the bus before the daemon subscribes to it
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let login1 = runtime.block_on(async {
+        let connection = bus_connection(&address, "org.freedesktop.DBus").await;
+        connection
+            .request_name("org.freedesktop.login1")
+            .await
+            .unwrap();
+        connection
+    });
+    let _daemon = spawn_daemon_env(
+        &image,
+        &config,
+        &socket,
+        &[("DBUS_SYSTEM_BUS_ADDRESS", address.as_ref())],
+    );
+    assert_eq!(stdout(&client(&config, &["-f"])), "eco");
+
+    // EC reset its settings during suspend
+    _ = gigacenter(&image, &["-f", "power"]);
+    _ = gigacenter(&image, &["-b", "60"]);
+    // The daemon may subscribe after the first signal, so resume is repeated until it reacts
+    let start = Instant::now();
+    while stdout(&client(&config, &["-f"])) != "eco" {
+        assert!(
+            start.elapsed() < Duration::from_secs(15),
+            "Settings weren't restored after resume"
+        );
+        runtime.block_on(async {
+            login1
+                .emit_signal(
+                    None::<()>,
+                    "/org/freedesktop/login1",
+                    "org.freedesktop.login1.Manager",
+                    "PrepareForSleep",
+                    &(false,),
+                )
+                .await
+                .unwrap();
+        });
+        sleep(Duration::from_secs(1));
+    }
+    assert_eq!(stdout(&client(&config, &["-b"])), "80");
+}
+
+#[test]
+fn fan_curves_follow_temperature() {
+    let dir = test_dir("fan_curves_follow_temperature");
+    let image = ec_image(&dir, &[(0x60, 40), (0x61, 40)]);
+    let curves = "50:0,80:100/50:0,80:100;hysteresis=0,dwell=0,ramp=0";
+    let (config, socket) = daemon_config(
+        &dir,
+        &format!("fan_curves = \"{curves}\"\npolling_interval_ms = 2500"),
+    );
+    let daemon = spawn_daemon(&image, &config, &socket);
+    let client = |args: &[&str]| client(&config, args);
+    assert_eq!(stdout(&client(&["-c"])), curves);
+
+    // Below the first point both fans get the minimum fixed speed
+    wait_for(|| reg(&image, 0xB0) == 0x39 && reg(&image, 0xB1) == 0x39);
+    set_reg(&image, 0x60, 80);
+    wait_for(|| reg(&image, 0xB0) == 0xE5);
+    assert_eq!(reg(&image, 0xB1), 0x39);
+    // Halfway between points is 50%
+    set_reg(&image, 0x61, 65);
+    wait_for(|| reg(&image, 0xB1) == 0x8F);
+    assert_eq!(stdout(&client(&["-f"])), "fixed:100,50");
+
+    // Manual fan mode disables curves, also after restart
+    _ = client(&["-f", "eco"]);
+    assert_eq!(stdout(&client(&["-c"])), "off");
+    drop(daemon);
+    std::fs::remove_file(&socket).unwrap();
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    assert_eq!(stdout(&client(&["-c"])), "off");
+    assert_eq!(stdout(&client(&["-f"])), "eco");
+}
+
+/// Wait for the condition checked by daemon in background
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Condition wasn't met in time"
+        );
+        sleep(Duration::from_millis(100));
+    }
+}
+
+/// Fake AC adapter in power supply directory
+fn set_ac_online(dir: &Path, online: bool) {
+    let adapter = dir.join("power_supply/AC");
+    std::fs::create_dir_all(&adapter).unwrap();
+    std::fs::write(adapter.join("type"), "Mains\n").unwrap();
+    // Renamed in place, so the daemon never reads half-written file
+    let tmp = adapter.join("online.tmp");
+    std::fs::write(&tmp, if online { "1\n" } else { "0\n" }).unwrap();
+    std::fs::rename(tmp, adapter.join("online")).unwrap();
+}
+
+#[test]
+fn power_rules_follow_power_source() {
+    let dir = test_dir("power_rules_follow_power_source");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(
+        &dir,
+        "[power.ac]\nfan_mode = \"power\"\n[power.battery]\nfan_mode = \"eco\"\nbat_threshold = 80\n",
+    );
+    set_ac_online(&dir, true);
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    let client = |args: &[&str]| client(&config, args);
+
+    // Power source detected at startup gets its rule applied
+    wait_for(|| stdout(&client(&["-f"])) == "power");
+    assert_eq!(stdout(&client(&["-b"])), "100");
+
+    set_ac_online(&dir, false);
+    wait_for(|| stdout(&client(&["-f"])) == "eco");
+    assert_eq!(stdout(&client(&["-b"])), "80");
+
+    // Rule of the current power source is applied right away
+    _ = client(&["power", "battery", "--bat-threshold", "70"]);
+    assert_eq!(stdout(&client(&["-b"])), "70");
+    assert_eq!(
+        stdout(&client(&["power", "battery"])),
+        "fan_mode=eco bat_threshold=70"
+    );
+
+    _ = client(&["power", "ac", "--clear"]);
+    assert_eq!(
+        stdout(&client(&["power"])),
+        "ac: not set\nbattery: fan_mode=eco bat_threshold=70"
+    );
+    // Unset fan mode of the rule leaves it as is, the threshold shows the switch was handled
+    _ = client(&["power", "ac", "--bat-threshold", "100"]);
+    assert_eq!(stdout(&client(&["power", "ac"])), "bat_threshold=100");
+    assert_eq!(stdout(&client(&["-b"])), "70");
+    set_ac_online(&dir, true);
+    wait_for(|| stdout(&client(&["-b"])) == "100");
+    assert_eq!(stdout(&client(&["-f"])), "eco");
+}
+
+#[test]
+fn power_rules_keep_fan_curves() {
+    let dir = test_dir("power_rules_keep_fan_curves");
+    let image = ec_image(&dir, &[(0x60, 40), (0x61, 40)]);
+    let curves = "50:0,80:100/50:0,80:100;hysteresis=0,dwell=0,ramp=0";
+    let (config, socket) = daemon_config(
+        &dir,
+        &format!(
+            "fan_curves = \"{curves}\"\n[power.ac]\nfan_mode = \"power\"\nbat_threshold = 90\n"
+        ),
+    );
+    set_ac_online(&dir, true);
+    let client = |args: &[&str]| client(&config, args);
+
+    // Fan mode of the rule would disable curves on every boot, so only the threshold is applied
+    for _ in 0..2 {
+        let _daemon = spawn_daemon(&image, &config, &socket);
+        wait_for(|| stdout(&client(&["-b"])) == "90");
+        assert_eq!(stdout(&client(&["-c"])), curves);
+        wait_for(|| reg(&image, 0xB0) == 0x39 && reg(&image, 0xB1) == 0x39);
+        assert!(stdout(&client(&["-f"])).starts_with("fixed:"));
+        std::fs::remove_file(&socket).unwrap();
+        // Threshold must be applied again after restart
+        _ = gigacenter(&image, &["-b", "60"]);
+    }
+}
+
+#[test]
+fn watch_gets_pushed_changes() {
+    let dir = test_dir("watch_gets_pushed_changes");
+    let image = ec_image(&dir, &[(0x60, 42)]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon(&image, &config, &socket);
+
+    // Snapshots are rare, so the mode change must be pushed as event
+    let mut watch = Daemon(
+        Command::new(BIN)
+            .env("GIGACENTER_CONFIG", &config)
+            .env_remove("GIGACENTER_SOCKET")
+            .args(["watch", "--interval", "60000"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let rx = spawn_line_reader(&mut watch.0);
+    let mut screen = String::new();
+    let mut wait_screen = |expected: &str| {
+        let start = Instant::now();
+        while !screen.contains(expected) {
+            let left = Duration::from_secs(10).saturating_sub(start.elapsed());
+            match rx.recv_timeout(left) {
+                Ok(line) => screen.push_str(&(line + "\n")),
+                Err(_) => panic!("`{expected}` wasn't shown: {screen}"),
+            }
+        }
+    };
+
+    wait_screen("- CPU Temp: 42");
+    wait_screen("- Fan mode: normal");
+    _ = client(&config, &["-f", "turbo"]);
+    wait_screen("- Fan mode: turbo");
+}
+
+#[test]
+fn shorter_watch_interval_applies_right_away() {
+    let dir = test_dir("shorter_watch_interval_applies_right_away");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    let watch = |interval: &str| {
+        let mut watch = Daemon(
+            Command::new(BIN)
+                .env("GIGACENTER_CONFIG", &config)
+                .env_remove("GIGACENTER_SOCKET")
+                .args(["watch", "--interval", interval])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        let rx = spawn_line_reader(&mut watch.0);
+        (watch, rx)
+    };
+    let wait_line = |rx: &mpsc::Receiver<String>, expected: &str| {
+        let start = Instant::now();
+        while !rx
+            .recv_timeout(Duration::from_secs(10).saturating_sub(start.elapsed()))
+            .unwrap()
+            .starts_with(expected)
+        {}
+    };
+
+    // Daemon sleeps for the minute asked by the first subscriber
+    let (_slow, slow_rx) = watch("60000");
+    wait_line(&slow_rx, "Since start (1)");
+    let (_fast, fast_rx) = watch("500");
+    wait_line(&fast_rx, "Since start (3)");
+}
+
+#[test]
+fn watch_polls_ec_without_daemon() {
+    let dir = test_dir("watch_polls_ec_without_daemon");
+    let image = ec_image(&dir, &[(0x60, 40)]);
+
+    let mut watch = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["--force-model", MODEL, "watch", "--interval", "100"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let rx = spawn_line_reader(&mut watch);
+    let mut screen = String::new();
+    let mut wait_screen = |expected: &str| {
+        let start = Instant::now();
+        // Piped output gets snapshots appended instead of redrawn
+        while !screen.contains(expected) {
+            let left = Duration::from_secs(10).saturating_sub(start.elapsed());
+            match rx.recv_timeout(left) {
+                Ok(line) => screen.push_str(&(line + "\n")),
+                Err(_) => panic!("`{expected}` wasn't shown: {screen}"),
+            }
+        }
+    };
+
+    wait_screen("- CPU Temp: 40");
+    let mut ec = std::fs::read(&image).unwrap();
+    ec[0x60] = 60;
+    std::fs::write(&image, ec).unwrap();
+    wait_screen("- CPU Temp: 60");
+    wait_screen(&format!("{:<24}{:>6}{:>6}", "CPU Temp", 40, 60));
+    assert!(!screen.contains('\x1b'), "{screen}");
+
+    // Ctrl-C is a normal way to stop watching
+    _ = unsafe { libc::kill(watch.id() as i32, libc::SIGINT) };
+    let status = watch.wait().unwrap();
+    assert!(status.success(), "{status}");
+}
+
+#[test]
+fn fan_cycle_switches_to_next_mode() {
+    let dir = test_dir("fan_cycle_switches_to_next_mode");
+    let image = ec_image(&dir, &[]);
+
+    assert_eq!(stdout(&gigacenter(&image, &["fan", "cycle"])), "eco");
+    assert_eq!(stdout(&gigacenter(&image, &["fan", "cycle"])), "power");
+    assert_eq!(stdout(&gigacenter(&image, &["-f"])), "power");
+    // Mode out of the list switches to the first one
+    let cycle = gigacenter(&image, &["fan", "cycle", "--modes", "eco,turbo"]);
+    assert_eq!(stdout(&cycle), "eco");
+    let cycle = gigacenter(
+        &image,
+        &["fan", "cycle", "--modes", "eco,turbo", "--format", "json"],
+    );
+    assert_eq!(stdout(&cycle), r#"{"fan_mode":"turbo"}"#);
+}
+
+/// The first lines printed by `gigacenter bar`
+fn bar_lines(image: &Path, args: &[&str], count: usize) -> Vec<String> {
+    let mut bar = Daemon(
+        Command::new(BIN)
+            .env_remove("GIGACENTER_EC_IMAGE")
+            .arg("--ec-image")
+            .arg(image)
+            .args(["--force-model", MODEL, "bar", "--interval", "100"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let rx = spawn_line_reader(&mut bar.0);
+    (0..count)
+        .map(|_| rx.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect()
+}
+
+#[test]
+fn bar_classes_follow_temperature_and_fan_mode() {
+    let dir = test_dir("bar_classes_follow_temperature_and_fan_mode");
+    let image = ec_image(&dir, &[(0x60, 85), (0x61, 50)]);
+    _ = gigacenter(&image, &["-f", "eco"]);
+
+    let waybar = bar_lines(&image, &[], 1);
+    assert!(
+        waybar[0].starts_with(r#"{"text":"85°C 50°C eco","alt":"eco","tooltip":"#),
+        "{waybar:?}"
+    );
+    assert!(
+        waybar[0].ends_with(r#""class":["warning","fan-eco"]}"#),
+        "{waybar:?}"
+    );
+
+    let plain = bar_lines(&image, &["--style", "plain", "--warning-temp", "90"], 1);
+    assert_eq!(plain, ["85°C 50°C eco"]);
+
+    let i3bar = bar_lines(&image, &["--style", "i3bar", "--critical-temp", "85"], 3);
+    assert_eq!(i3bar[..2], [r#"{"version":1}"#, "["]);
+    assert!(i3bar[2].contains(r#""instance":"fan-eco""#), "{i3bar:?}");
+    assert!(i3bar[2].contains(r#""urgent":true"#), "{i3bar:?}");
+}
+
+/// Hello as sent by client or daemon of the given protocol version
+fn hello(major: u16, minor: u16) -> [u8; 16] {
+    let mut hello = [0; 16];
+    hello[..4].copy_from_slice(b"GIGA");
+    hello[4..6].copy_from_slice(&major.to_le_bytes());
+    hello[6..8].copy_from_slice(&minor.to_le_bytes());
+    hello
+}
+
+#[test]
+fn protocol_handshake() {
+    let dir = test_dir("protocol_handshake");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon(&image, &config, &socket);
+
+    // Newer minor version of the same major one is accepted
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream.write_all(&hello(1, 99)).unwrap();
+    let mut reply = [0; 16];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply[..6], &hello(1, 0)[..6]);
+
+    // Daemon answers incompatible client with its version and hangs up
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream.write_all(&hello(99, 0)).unwrap();
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply[..6], &hello(1, 0)[..6]);
+    assert_eq!(stream.read(&mut reply).unwrap(), 0);
+
+    // Client of release without handshake sends frame length first
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream
+        .write_all(&[8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+        .unwrap();
+    stream.write_all(&[0; 4]).unwrap();
+    assert_eq!(stream.read(&mut reply).unwrap(), 0);
+
+    // Silent client is hung up on instead of holding the connection
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream.write_all(&hello(1, 0)[..4]).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert_eq!(stream.read(&mut reply).unwrap(), 0);
+}
+
+#[test]
+fn client_rejects_incompatible_daemon() {
+    let dir = test_dir("client_rejects_incompatible_daemon");
+    let (config, socket) = daemon_config(&dir, "");
+    let listener = UnixListener::bind(&socket).unwrap();
+    _ = std::thread::spawn(move || {
+        // Daemon of the next major version
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut client_hello = [0; 16];
+        stream.read_exact(&mut client_hello).unwrap();
+        stream.write_all(&hello(2, 0)).unwrap();
+        // Daemon of release without handshake waits for the rest of "frame"
+        let (_stream, _) = listener.accept().unwrap();
+        sleep(Duration::from_secs(10));
+    });
+
+    for expected in ["Daemon speaks protocol 2.0", "older release"] {
+        let output = Command::new(BIN)
+            .env("GIGACENTER_CONFIG", &config)
+            .env_remove("GIGACENTER_SOCKET")
+            .args(["power"])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains(expected), "{stderr}");
+    }
+}
+
+#[test]
+fn daemon_reports_error_reason() {
+    let dir = test_dir("daemon_reports_error_reason");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "");
+    // Unknown model, so daemon is read-only
+    let content = std::fs::read_to_string(&config).unwrap();
+    std::fs::write(
+        &config,
+        content.replace(&format!("model = \"{MODEL}\"\n"), ""),
+    )
+    .unwrap();
+    let _daemon = spawn_daemon(&image, &config, &socket);
+
+    let output = Command::new(BIN)
+        .env("GIGACENTER_CONFIG", &config)
+        .env_remove("GIGACENTER_SOCKET")
+        .args(["-f", "eco"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Unsupported on this model"), "{stderr}");
+    assert_eq!(reg(&image, 0x08), 0);
+    // Reads are still served
+    assert_eq!(stdout(&client(&config, &["-f"])), "normal");
+}
+
+#[test]
+#[ignore = "needs root to impersonate users"]
+fn regular_users_read_profile_registers_only() {
+    let dir = test_dir("regular_users_read_profile_registers_only");
+    let image = ec_image(&dir, &[(0x50, 0x42), (0x60, 50)]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    let nobody = |args: &[&str]| {
+        Command::new(BIN)
+            .env("GIGACENTER_CONFIG", &config)
+            .env_remove("GIGACENTER_SOCKET")
+            .uid(65534)
+            .gid(65534)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let show = nobody(&["-s"]);
+    assert!(show.status.success());
+    assert!(
+        stdout(&show).contains("- CPU Temp: 50"),
+        "{}",
+        stdout(&show)
+    );
+
+    let output = nobody(&["ec", "read", "0x50"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Permission denied"), "{stderr}");
+    assert_eq!(stdout(&client(&config, &["ec", "read", "0x50"])), "0x42");
+}
+
+#[test]
+#[ignore = "needs root to impersonate users"]
+fn socket_group_members_change_settings() {
+    let dir = test_dir("socket_group_members_change_settings");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "");
+    let nobody = |gid: u32, args: &[&str]| {
+        Command::new(BIN)
+            .env("GIGACENTER_CONFIG", &config)
+            .env_remove("GIGACENTER_SOCKET")
+            .uid(65534)
+            .gid(gid)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    {
+        let _daemon = spawn_daemon(&image, &config, &socket);
+        let output = nobody(65534, &["-f", "eco"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success());
+        assert!(stderr.contains("Permission denied"), "{stderr}");
+        assert_eq!(stdout(&nobody(65534, &["-f"])), "normal");
+    }
+
+    // [socket] table is the last one in config
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&config)
+        .unwrap();
+    writeln!(file, "group = \"nogroup\"").unwrap();
+    drop(file);
+    std::fs::remove_file(&socket).unwrap();
+    let _daemon = spawn_daemon(&image, &config, &socket);
+    assert!(nobody(65534, &["-f", "eco"]).status.success());
+    assert_eq!(stdout(&nobody(65534, &["-f"])), "eco");
+    assert_eq!(std::fs::metadata(&socket).unwrap().gid(), 65534);
+
+    // Supplementary group counts too. std can't set them for child, so setpriv does
+    let output = Command::new("setpriv")
+        .args(["--reuid=65534", "--regid=100", "--groups=65534", BIN])
+        .args(["-f", "power"])
+        .env("GIGACENTER_CONFIG", &config)
+        .env_remove("GIGACENTER_SOCKET")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&nobody(65534, &["-f"])), "power");
+}
+
+#[test]
+fn systemd_socket_activation_and_notify() {
+    let dir = test_dir("systemd_socket_activation_and_notify");
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(&dir, "");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let inode = std::fs::metadata(&socket).unwrap().ino();
+    let notify = UnixDatagram::bind(dir.join("notify")).unwrap();
+    notify
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let fd = listener.as_raw_fd();
+
+    let mut command = Command::new("sh");
+    // systemd sets LISTEN_PID to the pid of the daemon, which is known only after fork
+    _ = command
+        .args(["-c", "LISTEN_PID=$$ exec \"$0\" \"$@\"", BIN])
+        .env("GIGACENTER_CONFIG", &config)
+        .env(
+            "GIGACENTER_POWER_SUPPLY",
+            config.with_file_name("power_supply"),
+        )
+        .env_remove("GIGACENTER_SOCKET")
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .env("LISTEN_FDS", "1")
+        .env("NOTIFY_SOCKET", dir.join("notify"))
+        .env("WATCHDOG_USEC", "300000")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["--daemon", "run"]);
+    unsafe {
+        _ = command.pre_exec(move || {
+            // Passed sockets start from fd 3
+            let res = if fd == 3 {
+                libc::fcntl(3, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if res < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let _daemon = Daemon(command.spawn().unwrap());
+
+    let mut buf = [0; 64];
+    let len = notify.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1");
+    let len = notify.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"WATCHDOG=1");
+
+    _ = client(&config, &["-f", "eco"]);
+    assert_eq!(stdout(&client(&config, &["-f"])), "eco");
+    // Passed socket is used as is, not replaced by a new one
+    assert_eq!(std::fs::metadata(&socket).unwrap().ino(), inode);
+}
+
+#[zbus::proxy(
+    interface = "org.gigacenter.Daemon",
+    default_service = "org.gigacenter.Daemon",
+    default_path = "/org/gigacenter/Daemon"
+)]
+trait GigaCenter {
+    #[zbus(property)]
+    fn fan_mode(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn cpu_temp(&self) -> zbus::Result<u8>;
+    fn set_fan_mode(&self, fan_mode: &str) -> zbus::Result<()>;
+    fn set_bat_threshold(&self, threshold: u8) -> zbus::Result<()>;
+}
+
+/// Tests on private bus are skipped where dbus-daemon is missing, unless
+/// `GIGACENTER_REQUIRE_DBUS` is set, e.g. in CI
+fn skip_without_dbus(test: &str) {
+    assert!(
+        std::env::var_os("GIGACENTER_REQUIRE_DBUS").is_none(),
+        "dbus-daemon is missing"
+    );
+    eprintln!("skipped {test}: dbus-daemon is missing");
+}
+
+/// Private system bus in `dir`. `None` if dbus-daemon is missing
+fn private_bus(dir: &Path) -> Option<(Daemon, String)> {
+    let bus = dir.join("bus");
+    let bus_config = dir.join("bus.conf");
+    std::fs::write(
+        &bus_config,
+        format!(
+            r#"<busconfig>
+  <type>system</type>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow user="*"/>
+    <allow own="*"/>
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+  </policy>
+</busconfig>"#,
+            bus.display()
+        ),
+    )
+    .unwrap();
+    let bus_daemon = Command::new("dbus-daemon")
+        .arg("--nofork")
+        .arg(format!("--config-file={}", bus_config.display()))
+        .spawn()
+        .ok()?;
+    let bus_daemon = Daemon(bus_daemon);
+    let start = Instant::now();
+    while !bus.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Bus didn't start"
+        );
+        sleep(Duration::from_millis(50));
+    }
+    Some((bus_daemon, format!("unix:path={}", bus.display())))
+}
+
+/// Connect to the bus once `name` is owned there
+async fn bus_connection(address: &str, name: &str) -> zbus::Connection {
+    let connection = zbus::connection::Builder::address(address)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let dbus = zbus::fdo::DBusProxy::new(&connection).await.unwrap();
+    let start = Instant::now();
+    while !dbus.name_has_owner(name.try_into().unwrap()).await.unwrap() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Daemon didn't own {name}"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    connection
+}
+
+#[test]
+fn dbus_interface() {
+    let dir = test_dir("dbus_interface");
+    let Some((_bus_daemon, address)) = private_bus(&dir) else {
+        return skip_without_dbus("dbus_interface");
+    };
+    let image = ec_image(&dir, &[(0x60, 55)]);
+    let (config, socket) = daemon_config(&dir, "");
+    let _daemon = spawn_daemon_env(
+        &image,
+        &config,
+        &socket,
+        &[("DBUS_SYSTEM_BUS_ADDRESS", address.as_ref())],
+    );
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let connection = bus_connection(&address, "org.gigacenter.Daemon").await;
+        let proxy = GigaCenterProxy::new(&connection).await.unwrap();
+        assert_eq!(proxy.cpu_temp().await.unwrap(), 55);
+        assert_eq!(proxy.fan_mode().await.unwrap(), "normal");
+        // Power profiles bridge would keep power-profiles-daemon from starting, so it's opt-in
+        let bus = zbus::fdo::DBusProxy::new(&connection).await.unwrap();
+        let ppd_name = "net.hadess.PowerProfiles".try_into().unwrap();
+        assert!(!bus.name_has_owner(ppd_name).await.unwrap());
+
+        // Changes made by socket clients are signalled
+        let mut changes = proxy.receive_fan_mode_changed().await;
+        let config = config.clone();
+        _ = tokio::task::spawn_blocking(move || client(&config, &["-f", "power"]))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(change) = futures::StreamExt::next(&mut changes).await {
+                if change.get().await.unwrap() == "power" {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("No PropertiesChanged for FanMode");
+
+        proxy.set_fan_mode("eco").await.unwrap();
+        let err = proxy.set_bat_threshold(20).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("org.freedesktop.DBus.Error.InvalidArgs"),
+            "{err}"
+        );
+    });
+    assert_eq!(stdout(&client(&config, &["-f"])), "eco");
+}
+
+#[zbus::proxy(
+    interface = "net.hadess.PowerProfiles",
+    default_service = "net.hadess.PowerProfiles",
+    default_path = "/net/hadess/PowerProfiles"
+)]
+trait PowerProfiles {
+    #[zbus(property)]
+    fn active_profile(&self) -> zbus::Result<String>;
+    #[zbus(property)]
+    fn set_active_profile(&self, profile: &str) -> zbus::Result<()>;
+    #[zbus(property)]
+    fn active_profile_holds(
+        &self,
+    ) -> zbus::Result<Vec<std::collections::HashMap<String, zbus::zvariant::OwnedValue>>>;
+    fn hold_profile(&self, profile: &str, reason: &str, application_id: &str) -> zbus::Result<u32>;
+    fn release_profile(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+#[test]
+fn power_profiles_follow_fan_mode() {
+    let dir = test_dir("power_profiles_follow_fan_mode");
+    let Some((_bus_daemon, address)) = private_bus(&dir) else {
+        return skip_without_dbus("power_profiles_follow_fan_mode");
+    };
+    let image = ec_image(&dir, &[]);
+    let (config, socket) = daemon_config(
+        &dir,
+        "[power_profiles]\nenabled = true\nperformance = \"turbo\"",
+    );
+    let _daemon = spawn_daemon_env(
+        &image,
+        &config,
+        &socket,
+        &[("DBUS_SYSTEM_BUS_ADDRESS", address.as_ref())],
+    );
+    let fan_mode = {
+        let config = config.clone();
+        move || stdout(&client(&config, &["-f"]))
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let connection = bus_connection(&address, "net.hadess.PowerProfiles").await;
+        let proxy = PowerProfilesProxy::builder(&connection)
+            .cache_properties(zbus::proxy::CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(proxy.active_profile().await.unwrap(), "balanced");
+
+        proxy.set_active_profile("power-saver").await.unwrap();
+        assert_eq!(fan_mode(), "eco");
+        _ = client(&config, &["-f", "turbo"]);
+        assert_eq!(proxy.active_profile().await.unwrap(), "performance");
+
+        // Hold switches profile until released
+        let cookie = proxy
+            .hold_profile("power-saver", "test", "tests")
+            .await
+            .unwrap();
+        assert_eq!(fan_mode(), "eco");
+        // Unknown profile is rejected before holds are cancelled
+        assert!(proxy.set_active_profile("silent").await.is_err());
+        assert_eq!(proxy.active_profile_holds().await.unwrap().len(), 1);
+        proxy.release_profile(cookie).await.unwrap();
+        assert_eq!(fan_mode(), "turbo");
+        assert!(proxy.active_profile_holds().await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn machine_readable_output() {
+    let dir = test_dir("machine_readable_output");
+    let image = ec_image(&dir, &[(0x60, 50), (0x61, 45)]);
+    _ = gigacenter(&image, &["-f", "eco"]);
+
+    assert_eq!(
+        stdout(&gigacenter(&image, &["-s", "--format", "json"])),
+        r#"{"fan_mode":"eco","bat_threshold_percent":100,"cpu_temp_c":50,"gpu_temp_c":45,"cpu_fan_speed_rpm":0,"gpu_fan_speed_rpm":0}"#
+    );
+    assert_eq!(
+        stdout(&gigacenter(&image, &["-f", "--format", "env"])),
+        "FAN_MODE='eco'"
+    );
+    assert_eq!(
+        stdout(&gigacenter(&image, &["-b", "--format", "json"])),
+        r#"{"bat_threshold_percent":100}"#
+    );
+    // Text stays as it was
+    assert_eq!(stdout(&gigacenter(&image, &["-f"])), "eco");
+
+    // Errors are reported in the format with exit code of their class
+    let output = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["-f", "power", "--format", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(6));
+    assert!(
+        stdout(&output).starts_with(r#"{"error":{"class":"unsupported","message":"#),
+        "{}",
+        stdout(&output)
+    );
+    // Usage errors too
+    let output = Command::new(BIN)
+        .env_remove("GIGACENTER_EC_IMAGE")
+        .arg("--ec-image")
+        .arg(&image)
+        .args(["--format", "json", "-b", "200"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stdout(&output).starts_with(r#"{"error":{"class":"invalid_argument","message":"#),
+        "{}",
+        stdout(&output)
+    );
+}